@@ -1,12 +1,28 @@
+use ap_rpc::TransactionStatus;
 use codec::{Decode, Encode};
+use parking_lot::Mutex;
 use sc_client_api::backend::AuxStore;
 use sp_blockchain::{Error as ClientError, Result as ClientResult};
-use sp_core::H256;
-use sp_runtime::traits::Block as BlockT;
+use sp_core::{H256, U256};
+use sp_runtime::traits::{Block as BlockT, NumberFor, UniqueSaturatedInto};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Version of the aux-db layout written by this client.
+///
+/// 1. Ethereum block and transaction hash mappings (implicit, no version key).
+/// 2. Adds the mapping journal, the Ethereum block store keyed by Ethereum block hash with its
+///    number index, and the worker progress keys.
+pub const CURRENT_SCHEMA_VERSION: u32 = 2;
+
+const SCHEMA_VERSION_KEY: &[u8] = b"ethereum_schema_version";
 
 fn load_decode<B: AuxStore, T: Decode>(backend: &B, key: &[u8]) -> ClientResult<Option<T>> {
     let corrupt = |e: codec::Error| {
-        ClientError::Backend(format!("Frontier DB is corrupted. Decode error: {}", e))
+        ClientError::Backend(format!(
+            "Frontier DB is corrupted. Decode error at key {:?}: {}",
+            String::from_utf8_lossy(key),
+            e
+        ))
     };
     match backend.get_aux(key)? {
         None => Ok(None),
@@ -28,25 +44,6 @@ pub fn load_block_hash<Block: BlockT, B: AuxStore>(
     load_decode(backend, &key)
 }
 
-pub fn write_block_hash<Hash: Encode + Decode, F, R, Backend: AuxStore>(
-    client: &Backend,
-    ethereum_hash: H256,
-    block_hash: Hash,
-    write_aux: F,
-) -> ClientResult<R>
-    where
-        F: FnOnce(&[(&[u8], &[u8])]) -> R,
-{
-    let key = block_hash_key(ethereum_hash);
-    let mut data: Vec<Hash> = match load_decode(client, &key) {
-        Ok(Some(hashes)) => hashes,
-        Ok(None) => Vec::new(),
-        Err(e) => return Err(e),
-    };
-    data.push(block_hash);
-    Ok(write_aux(&[(&key, &data.encode()[..])]))
-}
-
 pub fn transaction_metadata_key(ethereum_transaction_hash: H256) -> Vec<u8> {
     let mut ret = b"ethereum_transaction_hash:".to_vec();
     ret.append(&mut ethereum_transaction_hash.as_ref().to_vec());
@@ -61,21 +58,486 @@ pub fn load_transaction_metadata<B: AuxStore>(
     load_decode(backend, &key)
 }
 
-pub fn write_transaction_metadata<F, R, Backend: AuxStore>(
-    client: &Backend,
-    hash: H256,
-    metadata: (H256, u32),
+/// Serializes read-modify-write updates of the mapping keys between block import, the
+/// mapping-sync worker and finality compaction.
+pub(crate) static WRITE_LOCK: Mutex<()> = parking_lot::const_mutex(());
+
+/// Mappings written for one block, kept by block number until the block's height is finalized.
+#[derive(Clone, Encode, Decode)]
+pub struct MappingJournalEntry<Hash> {
+    pub block_hash: Hash,
+    pub ethereum_block_hash: H256,
+    pub transaction_hashes: Vec<H256>,
+}
+
+pub fn mapping_journal_key<Number: Encode>(number: Number) -> Vec<u8> {
+    let mut ret = b"ethereum_mapping_journal:".to_vec();
+    ret.append(&mut number.encode());
+    ret
+}
+
+/// Map `ethereum_block_hash` and its transactions to `block_hash` in a single aux write.
+/// Callers must hold `WRITE_LOCK` until the write is committed.
+pub fn write_block_mappings<Block: BlockT, B: AuxStore, F, R>(
+    backend: &B,
+    number: NumberFor<Block>,
+    block_hash: Block::Hash,
+    ethereum_block_hash: H256,
+    transaction_hashes: Vec<H256>,
     write_aux: F,
 ) -> ClientResult<R>
     where
         F: FnOnce(&[(&[u8], &[u8])]) -> R,
 {
-    let key = transaction_metadata_key(hash);
-    let mut data: Vec<(H256, u32)> = match load_decode(client, &key) {
-        Ok(Some(metadata)) => metadata,
-        Ok(None) => Vec::new(),
-        Err(e) => return Err(e),
+    let mut inserts: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+
+    let key = block_hash_key(ethereum_block_hash);
+    let mut hashes: Vec<Block::Hash> = load_decode(backend, &key)?.unwrap_or_default();
+    if !hashes.contains(&block_hash) {
+        hashes.push(block_hash);
+        inserts.push((key, hashes.encode()));
+    }
+
+    for (index, transaction_hash) in transaction_hashes.iter().enumerate() {
+        let key = transaction_metadata_key(*transaction_hash);
+        let metadata = (ethereum_block_hash, index as u32);
+        let mut data: Vec<(H256, u32)> = load_decode(backend, &key)?.unwrap_or_default();
+        if !data.contains(&metadata) {
+            data.push(metadata);
+            inserts.push((key, data.encode()));
+        }
+    }
+
+    let key = mapping_journal_key(number);
+    let mut journal: Vec<MappingJournalEntry<Block::Hash>> =
+        load_decode(backend, &key)?.unwrap_or_default();
+    if !journal.iter().any(|entry| entry.block_hash == block_hash) {
+        journal.push(MappingJournalEntry {
+            block_hash,
+            ethereum_block_hash,
+            transaction_hashes,
+        });
+        inserts.push((key, journal.encode()));
+    }
+
+    let inserts = inserts
+        .iter()
+        .map(|(k, v)| (&k[..], &v[..]))
+        .collect::<Vec<_>>();
+    Ok(write_aux(&inserts))
+}
+
+/// Aux writes staged in memory, so that several updates of one key compose before committing.
+struct StagedAux<'a, B> {
+    backend: &'a B,
+    inserts: BTreeMap<Vec<u8>, Vec<u8>>,
+    deletes: BTreeSet<Vec<u8>>,
+}
+
+impl<'a, B: AuxStore> StagedAux<'a, B> {
+    fn new(backend: &'a B) -> Self {
+        Self {
+            backend,
+            inserts: BTreeMap::new(),
+            deletes: BTreeSet::new(),
+        }
+    }
+
+    fn load<T: Decode>(&self, key: &[u8]) -> ClientResult<Option<T>> {
+        match self.inserts.get(key) {
+            Some(data) => T::decode(&mut &data[..]).map(Some).map_err(|e| {
+                ClientError::Backend(format!("Frontier DB is corrupted. Decode error: {}", e))
+            }),
+            None if self.deletes.contains(key) => Ok(None),
+            None => load_decode(self.backend, key),
+        }
+    }
+
+    fn put<T: Encode>(&mut self, key: Vec<u8>, value: &[T]) {
+        if value.is_empty() {
+            self.inserts.remove(&key);
+            self.deletes.insert(key);
+        } else {
+            self.deletes.remove(&key);
+            self.inserts.insert(key, value.encode());
+        }
+    }
+
+    /// Stage an already encoded value, which unlike `put` may also be empty.
+    fn put_encoded(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.deletes.remove(&key);
+        self.inserts.insert(key, value);
+    }
+
+    fn delete(&mut self, key: Vec<u8>) {
+        self.inserts.remove(&key);
+        self.deletes.insert(key);
+    }
+
+    fn commit(self) -> ClientResult<()> {
+        let inserts = self
+            .inserts
+            .iter()
+            .map(|(k, v)| (&k[..], &v[..]))
+            .collect::<Vec<_>>();
+        let deletes = self.deletes.iter().map(|k| &k[..]).collect::<Vec<_>>();
+        self.backend.insert_aux(&inserts, &deletes)
+    }
+}
+
+/// Once the height `number` is final, reduce every mapping written for blocks at that height to
+/// the canonical block, drop mappings and stored Ethereum blocks that only belonged to retracted
+/// forks, and forget the journal. Callers must hold `WRITE_LOCK`.
+pub fn compact_block_mappings<Block: BlockT, B: AuxStore>(
+    backend: &B,
+    number: NumberFor<Block>,
+    canonical: Option<Block::Hash>,
+) -> ClientResult<()> {
+    let journal_key = mapping_journal_key(number);
+    let journal: Vec<MappingJournalEntry<Block::Hash>> = match load_decode(backend, &journal_key)? {
+        Some(journal) => journal,
+        None => return Ok(()),
     };
-    data.push(metadata);
-    Ok(write_aux(&[(&key, &data.encode()[..])]))
-}
\ No newline at end of file
+    let mut staged = StagedAux::new(backend);
+    staged.put::<MappingJournalEntry<Block::Hash>>(journal_key, &[]);
+
+    // Retracted forks may have produced the same Ethereum block as the canonical one.
+    let canonical_ethereum_hash = journal
+        .iter()
+        .find(|entry| Some(entry.block_hash) == canonical)
+        .map(|entry| entry.ethereum_block_hash);
+    let key = ethereum_block_number_key(U256::from(
+        UniqueSaturatedInto::<u128>::unique_saturated_into(number),
+    ));
+    let mut hashes: Vec<H256> = staged.load(&key)?.unwrap_or_default();
+    hashes.retain(|hash| Some(*hash) == canonical_ethereum_hash);
+    staged.put(key, &hashes);
+
+    for entry in journal {
+        let is_canonical = Some(entry.block_hash) == canonical;
+        let shares_canonical = Some(entry.ethereum_block_hash) == canonical_ethereum_hash;
+        if !shares_canonical {
+            staged.delete(ethereum_block_key(entry.ethereum_block_hash));
+            staged.delete(ethereum_receipts_key(entry.ethereum_block_hash));
+            staged.delete(ethereum_statuses_key(entry.ethereum_block_hash));
+        }
+
+        let key = block_hash_key(entry.ethereum_block_hash);
+        let mut hashes: Vec<Block::Hash> = staged.load(&key)?.unwrap_or_default();
+        if is_canonical {
+            hashes = vec![entry.block_hash];
+        } else {
+            hashes.retain(|hash| *hash != entry.block_hash);
+        }
+        staged.put(key, &hashes);
+
+        for (index, transaction_hash) in entry.transaction_hashes.into_iter().enumerate() {
+            let key = transaction_metadata_key(transaction_hash);
+            let metadata = (entry.ethereum_block_hash, index as u32);
+            let mut data: Vec<(H256, u32)> = staged.load(&key)?.unwrap_or_default();
+            if is_canonical {
+                data = vec![metadata];
+            } else if !shares_canonical {
+                data.retain(|m| *m != metadata);
+            }
+            staged.put(key, &data);
+        }
+    }
+
+    staged.commit()
+}
+
+const MAPPING_COMPACTED_KEY: &[u8] = b"ethereum_mapping_compacted";
+
+pub fn load_mapping_compacted<Block: BlockT, B: AuxStore>(
+    backend: &B,
+) -> ClientResult<Option<NumberFor<Block>>> {
+    load_decode(backend, MAPPING_COMPACTED_KEY)
+}
+
+pub fn write_mapping_compacted<Block: BlockT, B: AuxStore>(
+    backend: &B,
+    number: NumberFor<Block>,
+) -> ClientResult<()> {
+    backend.insert_aux(&[(MAPPING_COMPACTED_KEY, &number.encode()[..])], &[])
+}
+
+pub fn ethereum_block_key(ethereum_block_hash: H256) -> Vec<u8> {
+    let mut ret = b"ethereum_block:".to_vec();
+    ret.append(&mut ethereum_block_hash.as_ref().to_vec());
+    ret
+}
+
+pub fn ethereum_receipts_key(ethereum_block_hash: H256) -> Vec<u8> {
+    let mut ret = b"ethereum_receipts:".to_vec();
+    ret.append(&mut ethereum_block_hash.as_ref().to_vec());
+    ret
+}
+
+pub fn ethereum_statuses_key(ethereum_block_hash: H256) -> Vec<u8> {
+    let mut ret = b"ethereum_statuses:".to_vec();
+    ret.append(&mut ethereum_block_hash.as_ref().to_vec());
+    ret
+}
+
+pub fn ethereum_block_number_key(number: U256) -> Vec<u8> {
+    let mut ret = b"ethereum_block_number:".to_vec();
+    ret.append(&mut number.encode());
+    ret
+}
+
+pub fn load_ethereum_block<B: AuxStore>(
+    backend: &B,
+    ethereum_block_hash: H256,
+) -> ClientResult<Option<ethereum::Block>> {
+    load_decode(backend, &ethereum_block_key(ethereum_block_hash))
+}
+
+pub fn load_ethereum_receipts<B: AuxStore>(
+    backend: &B,
+    ethereum_block_hash: H256,
+) -> ClientResult<Option<Vec<ethereum::Receipt>>> {
+    load_decode(backend, &ethereum_receipts_key(ethereum_block_hash))
+}
+
+pub fn load_ethereum_statuses<B: AuxStore>(
+    backend: &B,
+    ethereum_block_hash: H256,
+) -> ClientResult<Option<Vec<TransactionStatus>>> {
+    load_decode(backend, &ethereum_statuses_key(ethereum_block_hash))
+}
+
+/// Hashes of the stored Ethereum blocks at height `number`, only the canonical one once the
+/// height is compacted.
+pub fn load_ethereum_block_hashes<B: AuxStore>(
+    backend: &B,
+    number: U256,
+) -> ClientResult<Vec<H256>> {
+    Ok(load_decode(backend, &ethereum_block_number_key(number))?.unwrap_or_default())
+}
+
+/// Store an Ethereum block with its receipts and statuses, and index it by number.
+/// Callers must hold `WRITE_LOCK`.
+pub fn write_ethereum_block_data<B: AuxStore>(
+    backend: &B,
+    block: &ethereum::Block,
+    receipts: &[ethereum::Receipt],
+    statuses: &[TransactionStatus],
+) -> ClientResult<()> {
+    let hash = block.header.hash();
+    let mut staged = StagedAux::new(backend);
+    let key = ethereum_block_number_key(block.header.number);
+    let mut hashes: Vec<H256> = staged.load(&key)?.unwrap_or_default();
+    if !hashes.contains(&hash) {
+        hashes.push(hash);
+        staged.put(key, &hashes);
+    }
+    staged.put_encoded(ethereum_block_key(hash), block.encode());
+    staged.put_encoded(ethereum_receipts_key(hash), receipts.encode());
+    staged.put_encoded(ethereum_statuses_key(hash), statuses.encode());
+    staged.commit()
+}
+
+const MAPPING_SYNC_TIP_KEY: &[u8] = b"ethereum_mapping_sync_tip";
+
+pub fn load_mapping_sync_tip<Block: BlockT, B: AuxStore>(
+    backend: &B,
+) -> ClientResult<Option<Block::Hash>> {
+    load_decode(backend, MAPPING_SYNC_TIP_KEY)
+}
+
+pub fn write_mapping_sync_tip<Block: BlockT, B: AuxStore>(
+    backend: &B,
+    hash: Block::Hash,
+) -> ClientResult<()> {
+    backend.insert_aux(&[(MAPPING_SYNC_TIP_KEY, &hash.encode()[..])], &[])
+}
+
+pub fn load_schema_version<B: AuxStore>(backend: &B) -> ClientResult<Option<u32>> {
+    load_decode(backend, SCHEMA_VERSION_KEY)
+}
+
+/// Bring the aux-db layout up to `CURRENT_SCHEMA_VERSION`, returning the version found.
+pub fn upgrade_schema<B: AuxStore>(backend: &B) -> ClientResult<u32> {
+    let found = load_schema_version(backend)?.unwrap_or(1);
+    if found > CURRENT_SCHEMA_VERSION {
+        return Err(ClientError::Backend(format!(
+            "Frontier DB schema version {} is newer than the supported version {}",
+            found, CURRENT_SCHEMA_VERSION
+        )));
+    }
+    let _write_lock = WRITE_LOCK.lock();
+    for version in found..CURRENT_SCHEMA_VERSION {
+        match version {
+            // Version 1 mappings may hold duplicate and retracted entries but have no journal.
+            // Restart the mapping sync and compaction from genesis so every canonical block is
+            // journaled again and compacted to its canonical entry.
+            1 => backend.insert_aux(&[], &[MAPPING_SYNC_TIP_KEY, MAPPING_COMPACTED_KEY])?,
+            _ => unreachable!("every version below the current one has an upgrade; qed"),
+        }
+        backend.insert_aux(&[(SCHEMA_VERSION_KEY, &(version + 1).encode()[..])], &[])?;
+    }
+    Ok(found)
+}
+
+/// Rewrite the mappings of one canonical block from chain data without reading the existing,
+/// possibly corrupted, entries. Callers must hold `WRITE_LOCK`.
+pub fn repair_block_mappings<Block: BlockT, B: AuxStore>(
+    backend: &B,
+    number: NumberFor<Block>,
+    block_hash: Block::Hash,
+    ethereum_block_hash: H256,
+    transaction_hashes: Vec<H256>,
+) -> ClientResult<()> {
+    let mut staged = StagedAux::new(backend);
+    staged.put(block_hash_key(ethereum_block_hash), &[block_hash]);
+    for (index, transaction_hash) in transaction_hashes.iter().enumerate() {
+        staged.put(
+            transaction_metadata_key(*transaction_hash),
+            &[(ethereum_block_hash, index as u32)],
+        );
+    }
+    staged.put(
+        mapping_journal_key(number),
+        &[MappingJournalEntry {
+            block_hash,
+            ethereum_block_hash,
+            transaction_hashes,
+        }],
+    );
+    staged.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sp_core::H160;
+    use sp_runtime::testing::{Block as TestBlock, ExtrinsicWrapper};
+
+    type Block = TestBlock<ExtrinsicWrapper<u64>>;
+
+    #[derive(Default)]
+    struct MemoryAux(Mutex<BTreeMap<Vec<u8>, Vec<u8>>>);
+
+    impl AuxStore for MemoryAux {
+        fn insert_aux<
+            'a,
+            'b: 'a,
+            'c: 'a,
+            I: IntoIterator<Item = &'a (&'c [u8], &'c [u8])>,
+            D: IntoIterator<Item = &'a &'b [u8]>,
+        >(
+            &self,
+            insert: I,
+            delete: D,
+        ) -> ClientResult<()> {
+            let mut aux = self.0.lock();
+            for (key, value) in insert {
+                aux.insert(key.to_vec(), value.to_vec());
+            }
+            for key in delete {
+                aux.remove(*key);
+            }
+            Ok(())
+        }
+
+        fn get_aux(&self, key: &[u8]) -> ClientResult<Option<Vec<u8>>> {
+            Ok(self.0.lock().get(key).cloned())
+        }
+    }
+
+    fn ethereum_block(beneficiary: u8) -> ethereum::Block {
+        let header = ethereum::PartialHeader {
+            parent_hash: H256::zero(),
+            beneficiary: H160::repeat_byte(beneficiary),
+            state_root: H256::zero(),
+            receipts_root: H256::zero(),
+            logs_bloom: Default::default(),
+            difficulty: U256::zero(),
+            number: U256::one(),
+            gas_limit: U256::zero(),
+            gas_used: U256::zero(),
+            timestamp: 0,
+            extra_data: Vec::new(),
+            mix_hash: H256::zero(),
+            nonce: Default::default(),
+        };
+        ethereum::Block::new(header, Vec::new(), Vec::new())
+    }
+
+    /// Write the mappings and the block store entries of `block` imported as `block_hash`.
+    fn import(backend: &MemoryAux, block_hash: H256, block: &ethereum::Block, transaction: H256) {
+        write_block_mappings::<Block, _, _, _>(
+            backend,
+            1,
+            block_hash,
+            block.header.hash(),
+            vec![transaction],
+            |insert| backend.insert_aux(insert, &[]),
+        )
+        .and_then(|res| res)
+        .unwrap();
+        write_ethereum_block_data(backend, block, &[], &[]).unwrap();
+    }
+
+    #[test]
+    fn compaction_keeps_only_the_canonical_block() {
+        let backend = MemoryAux::default();
+        let transaction = H256::repeat_byte(0x33);
+        let (canonical, retracted) = (H256::repeat_byte(0x01), H256::repeat_byte(0x02));
+        let (canonical_block, retracted_block) = (ethereum_block(0x0a), ethereum_block(0x0b));
+        let canonical_ethereum = canonical_block.header.hash();
+        let retracted_ethereum = retracted_block.header.hash();
+        import(&backend, canonical, &canonical_block, transaction);
+        import(&backend, retracted, &retracted_block, transaction);
+        assert_eq!(
+            load_ethereum_block_hashes(&backend, U256::one()).unwrap(),
+            vec![canonical_ethereum, retracted_ethereum]
+        );
+
+        compact_block_mappings::<Block, _>(&backend, 1, Some(canonical)).unwrap();
+
+        assert_eq!(
+            load_block_hash::<Block, _>(&backend, canonical_ethereum).unwrap(),
+            Some(vec![canonical])
+        );
+        assert_eq!(load_block_hash::<Block, _>(&backend, retracted_ethereum).unwrap(), None);
+        assert_eq!(
+            load_transaction_metadata(&backend, transaction).unwrap(),
+            Some(vec![(canonical_ethereum, 0)])
+        );
+        assert_eq!(
+            load_ethereum_block_hashes(&backend, U256::one()).unwrap(),
+            vec![canonical_ethereum]
+        );
+        assert!(load_ethereum_block(&backend, canonical_ethereum).unwrap().is_some());
+        assert!(load_ethereum_block(&backend, retracted_ethereum).unwrap().is_none());
+        assert!(backend.get_aux(&mapping_journal_key(1u64)).unwrap().is_none());
+    }
+
+    #[test]
+    fn compaction_keeps_an_ethereum_block_shared_with_a_retracted_fork() {
+        let backend = MemoryAux::default();
+        let transaction = H256::repeat_byte(0x33);
+        let block = ethereum_block(0x0a);
+        let ethereum_hash = block.header.hash();
+        import(&backend, H256::repeat_byte(0x01), &block, transaction);
+        import(&backend, H256::repeat_byte(0x02), &block, transaction);
+
+        compact_block_mappings::<Block, _>(&backend, 1, Some(H256::repeat_byte(0x01))).unwrap();
+
+        assert_eq!(
+            load_block_hash::<Block, _>(&backend, ethereum_hash).unwrap(),
+            Some(vec![H256::repeat_byte(0x01)])
+        );
+        assert_eq!(
+            load_transaction_metadata(&backend, transaction).unwrap(),
+            Some(vec![(ethereum_hash, 0)])
+        );
+        assert_eq!(
+            load_ethereum_block_hashes(&backend, U256::one()).unwrap(),
+            vec![ethereum_hash]
+        );
+        assert!(load_ethereum_block(&backend, ethereum_hash).unwrap().is_some());
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{aux_schema, find_frontier_log};
+use ap_rpc::{EthereumRuntimeRPCApi, TransactionStatus};
+use sc_client_api::backend::AuxStore;
+use sp_api::{BlockId, ProvideRuntimeApi};
+use sp_blockchain::{Error as ClientError, HeaderBackend, Result as ClientResult};
+use sp_core::{H256, U256};
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT, Zero};
+
+/// Copy the Ethereum block, receipts and transaction statuses of `hash` from runtime state into
+/// the client-side store, so RPC reads survive state pruning.
+pub fn store_ethereum_block<B, C>(client: &C, hash: B::Hash) -> ClientResult<()>
+    where
+        B: BlockT,
+        C: ProvideRuntimeApi<B> + AuxStore,
+        C::Api: EthereumRuntimeRPCApi<B>,
+{
+    if let Some((block, receipts, statuses)) = fetch_ethereum_block::<B, _>(client, hash)? {
+        let _write_lock = aux_schema::WRITE_LOCK.lock();
+        aux_schema::write_ethereum_block_data(client, &block, &receipts, &statuses)?;
+    }
+    Ok(())
+}
+
+/// Ethereum block, receipts and transaction statuses the runtime stored in the block `hash`, if
+/// its runtime includes `pallet_ethereum`.
+pub(crate) fn fetch_ethereum_block<B, C>(
+    client: &C,
+    hash: B::Hash,
+) -> ClientResult<Option<(ethereum::Block, Vec<ethereum::Receipt>, Vec<TransactionStatus>)>>
+    where
+        B: BlockT,
+        C: ProvideRuntimeApi<B>,
+        C::Api: EthereumRuntimeRPCApi<B>,
+{
+    let id = BlockId::Hash(hash);
+    if !crate::has_ethereum_api(client, &id)? {
+        return Ok(None);
+    }
+    let (block, receipts, statuses) = client
+        .runtime_api()
+        .current_all(&id)
+        .map_err(|err| {
+            ClientError::Backend(format!("fetch runtime Ethereum block failed: {:?}", err))
+        })?;
+    match (block, receipts, statuses) {
+        (Some(block), Some(receipts), Some(statuses)) => Ok(Some((block, receipts, statuses))),
+        _ => Ok(None),
+    }
+}
+
+/// Hash of the Ethereum block produced by the block `hash`, which keys the client-side store.
+pub fn ethereum_block_hash<B, C>(client: &C, hash: B::Hash) -> ClientResult<Option<H256>>
+    where
+        B: BlockT,
+        C: HeaderBackend<B> + AuxStore,
+{
+    let header = match client.header(BlockId::Hash(hash))? {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    if header.number().is_zero() {
+        // The genesis block carries no digest, but there is only one Ethereum genesis block.
+        return Ok(aux_schema::load_ethereum_block_hashes(client, U256::zero())?
+            .into_iter()
+            .next());
+    }
+    Ok(find_frontier_log::<B>(&header)
+        .ok()
+        .map(|(ethereum_block_hash, _)| ethereum_block_hash))
+}
@@ -0,0 +1,62 @@
+use crate::aux_schema;
+use futures::StreamExt;
+use log::*;
+use sc_client_api::{backend::AuxStore, BlockchainEvents};
+use sp_blockchain::{HeaderBackend, Result as ClientResult};
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT, NumberFor, One, Zero};
+use std::sync::Arc;
+
+/// Heights compacted between two yields to the executor.
+const COMPACTION_CHUNK_SIZE: u32 = 256;
+
+/// Compact the heights after the last compacted one up to `finalized`.
+///
+/// Keys written by version 1 of the schema for blocks that were retracted before the upgrade
+/// are not in any journal, so they are never removed. They are harmless: lookups skip entries
+/// that are not canonical.
+async fn compact_to<B, C>(client: &C, finalized: NumberFor<B>) -> ClientResult<()>
+    where
+        B: BlockT,
+        C: HeaderBackend<B> + AuxStore,
+{
+    let mut number = match aux_schema::load_mapping_compacted::<B, _>(client)? {
+        Some(compacted) => compacted + One::one(),
+        None => Zero::zero(),
+    };
+    let mut chunk = 0;
+    while number <= finalized {
+        {
+            let _write_lock = aux_schema::WRITE_LOCK.lock();
+            aux_schema::compact_block_mappings::<B, _>(client, number, client.hash(number)?)?;
+            aux_schema::write_mapping_compacted::<B, _>(client, number)?;
+        }
+        number = number + One::one();
+        chunk += 1;
+        if chunk == COMPACTION_CHUNK_SIZE {
+            chunk = 0;
+            crate::yield_now().await;
+        }
+    }
+    Ok(())
+}
+
+/// Compact the Ethereum hash mappings of every newly finalized height.
+///
+/// After a schema upgrade this walks the whole chain from genesis, so spawn it as a blocking
+/// task.
+pub async fn mapping_compaction_task<B, C>(client: Arc<C>)
+    where
+        B: BlockT,
+        C: HeaderBackend<B> + BlockchainEvents<B> + AuxStore,
+{
+    let mut notifications = client.finality_notification_stream();
+    while let Some(notification) = notifications.next().await {
+        let finalized = *notification.header.number();
+        if let Err(err) = compact_to::<B, _>(client.as_ref(), finalized).await {
+            warn!(
+                target: "frontier-consensus",
+                "Ethereum mapping compaction failed: {:?}", err,
+            );
+        }
+    }
+}
@@ -1,23 +1,44 @@
 mod aux_schema;
+mod block_store;
+mod compaction;
+mod mapping_sync;
+mod proposer;
 
-pub use crate::aux_schema::{load_block_hash, load_transaction_metadata};
+pub use crate::aux_schema::{
+    load_block_hash, load_ethereum_block, load_ethereum_block_hashes, load_ethereum_receipts,
+    load_ethereum_statuses, load_schema_version, load_transaction_metadata, upgrade_schema,
+    CURRENT_SCHEMA_VERSION,
+};
+pub use crate::block_store::{ethereum_block_hash, store_ethereum_block};
+pub use crate::compaction::mapping_compaction_task;
+pub use crate::mapping_sync::MappingSyncWorker;
+pub use crate::proposer::{PreBlockProposer, PreBlockProposerFactory};
 
-use ap_consensus::{ConsensusLog, FRONTIER_ENGINE_ID};
-use ap_rpc::EthereumRuntimeRPCApi;
+use ap_consensus::{
+    find_pre_block, ConsensusLog, PreBlockError, FRONTIER_ENGINE_ID, MAX_EXTRA_DATA_SIZE,
+};
+use ap_rpc::{EthereumRuntimeRPCApi, TransactionStatus};
 use log::*;
 use sc_client_api;
 use sc_client_api::{backend::AuxStore, BlockOf};
-use sp_api::{BlockId, ProvideRuntimeApi};
+use sha3::{Digest, Keccak256};
+use sp_api::{ApiExt, BlockId, ProvideRuntimeApi};
 use sp_block_builder::BlockBuilder as BlockBuilderApi;
-use sp_blockchain::{well_known_cache_keys::Id as CacheKeyId, HeaderBackend, ProvideCache};
+use sp_blockchain::{
+    well_known_cache_keys::Id as CacheKeyId, Error as ClientError, HeaderBackend, ProvideCache,
+    Result as ClientResult,
+};
 use sp_consensus::{
     BlockCheckParams, BlockImport, BlockImportParams, Error as ConsensusError, ImportResult,
 };
+use codec::Encode;
+use sp_core::{H160, H256};
 use sp_runtime::generic::OpaqueDigestItemId;
-use sp_runtime::traits::{Block as BlockT, Header as HeaderT, One, Zero};
+use sp_runtime::traits::{Block as BlockT, DigestItemFor, Header as HeaderT, One, Zero};
 use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::task::Poll;
 
 #[derive(derive_more::Display, Debug)]
 pub enum Error {
@@ -25,8 +46,16 @@ pub enum Error {
     MultiplePostRuntimeLogs,
     #[display(fmt = "Post-runtime Ethereum block not found, rejecting!")]
     NoPostRuntimeLog,
-    #[display(fmt = "Cannot access the runtime at genesis, rejecting!")]
-    RuntimeApiCallFailed,
+    #[display(fmt = "Multiple pre-runtime Ethereum headers, rejecting!")]
+    MultiplePreRuntimeLogs,
+    #[display(fmt = "Invalid pre-runtime Ethereum header, rejecting!")]
+    InvalidPreRuntimeLog,
+    #[display(fmt = "Block body is required to verify the Ethereum block, rejecting!")]
+    MissingBody,
+    #[display(fmt = "Post-runtime Ethereum block does not match the runtime state, rejecting!")]
+    EthereumBlockMismatch,
+    #[display(fmt = "Ethereum header extra data exceeds 32 bytes")]
+    ExtraDataTooLarge,
 }
 
 impl From<Error> for String {
@@ -45,6 +74,7 @@ pub struct FrontierBlockImport<B: BlockT, I, C> {
     inner: I,
     client: Arc<C>,
     enabled: bool,
+    strict: bool,
     _marker: PhantomData<B>,
 }
 
@@ -54,6 +84,7 @@ impl<Block: BlockT, I: Clone + BlockImport<Block>, C> Clone for FrontierBlockImp
             inner: self.inner.clone(),
             client: self.client.clone(),
             enabled: self.enabled,
+            strict: self.strict,
             _marker: PhantomData,
         }
     }
@@ -68,13 +99,31 @@ impl<B, I, C> FrontierBlockImport<B, I, C>
         C::Api: EthereumRuntimeRPCApi<B>,
         C::Api: BlockBuilderApi<B>,
 {
-    pub fn new(inner: I, client: Arc<C>, enabled: bool) -> Self {
-        Self {
+    /// With `strict`, blocks without a body are rejected, and once a block is imported the
+    /// Ethereum block its runtime stored is checked against the post-runtime and `PreBlock` logs,
+    /// whether the block was authored locally or came from the network. A block that fails the
+    /// check is already in the database, since it can only be read back after the inner import,
+    /// but its import fails and no mappings are written for it, so RPC never serves it.
+    ///
+    /// Fails when the Frontier DB cannot be brought up to the current schema, since writing
+    /// mappings in the current layout on top of an older one would corrupt it.
+    pub fn new(inner: I, client: Arc<C>, enabled: bool, strict: bool) -> ClientResult<Self> {
+        let version = aux_schema::upgrade_schema(client.as_ref())?;
+        if version < CURRENT_SCHEMA_VERSION {
+            info!(
+                target: "frontier-consensus",
+                "Upgraded Frontier DB schema from version {} to {}",
+                version,
+                CURRENT_SCHEMA_VERSION,
+            );
+        }
+        Ok(Self {
             inner,
             client,
             enabled,
+            strict,
             _marker: PhantomData,
-        }
+        })
     }
 }
 
@@ -96,92 +145,322 @@ impl<B, I, C> BlockImport<B> for FrontierBlockImport<B, I, C>
 
     fn import_block(
         &mut self,
-        mut block: BlockImportParams<B, Self::Transaction>,
+        block: BlockImportParams<B, Self::Transaction>,
         new_cache: HashMap<CacheKeyId, Vec<u8>>,
     ) -> Result<ImportResult, Self::Error> {
-        macro_rules! insert_closure {
-            () => {
-                |insert| {
-                    block
-                        .auxiliary
-                        .extend(insert.iter().map(|(k, v)| (k.to_vec(), Some(v.to_vec()))))
-                }
-            };
+        if !self.enabled {
+            return self
+                .inner
+                .import_block(block, new_cache)
+                .map_err(Into::into);
         }
 
         let client = self.client.clone();
+        let hash = block.post_hash();
+        let number = *block.header.number();
+        let parent = BlockId::Hash(*block.header.parent_hash());
+        let (block_hash, transaction_hashes) = match find_frontier_log::<B>(&block.header) {
+            Ok(log) => log,
+            // Blocks executed by a runtime without `pallet_ethereum` carry no Ethereum block.
+            // Without the parent state that cannot be told, so the log stays required.
+            Err(Error::NoPostRuntimeLog)
+                if matches!(has_ethereum_api(client.as_ref(), &parent), Ok(false)) =>
+            {
+                return self
+                    .inner
+                    .import_block(block, new_cache)
+                    .map_err(Into::into);
+            }
+            Err(err) => return Err(err.into()),
+        };
+        // The runtime rejects the same blocks, this only fails them before execution.
+        let pre_block = find_pre_log::<B>(&block.header)?;
+        // Without a body the inner import executes nothing, so nothing could be checked.
+        if self.strict && block.body.is_none() {
+            return Err(Error::MissingBody.into());
+        }
 
-        if self.enabled {
-            let log = find_frontier_log::<B>(&block.header)?;
-            let hash = block.post_hash();
-            match log {
-                ConsensusLog::EndBlock {
-                    block_hash,
-                    transaction_hashes,
-                } => {
-                    let res = aux_schema::write_block_hash(
-                        client.as_ref(),
+        let imported = self
+            .inner
+            .import_block(block, new_cache)
+            .map_err(Into::into)?;
+        if let ImportResult::Imported(_) = imported {
+            // Written after the import, so a crash in between leaves the block unmapped until
+            // `MappingSyncWorker` catches up, and the runtime calls below run without the lock.
+            // Stored here rather than on import notifications, which are not sent during major
+            // sync.
+            let ethereum_block = fetch_ethereum_block_or_warn(client.as_ref(), hash);
+            if self.strict {
+                match &ethereum_block {
+                    Some((ethereum_block, _, _)) => check_ethereum_block(
+                        ethereum_block,
                         block_hash,
-                        hash,
-                        insert_closure!(),
-                    );
-                    if res.is_err() {
-                        trace!(target: "frontier-consensus", "{:?}", res);
-                    }
-
-                    for (index, transaction_hash) in transaction_hashes.into_iter().enumerate() {
-                        let res = aux_schema::write_transaction_metadata(
-                            client.as_ref(),
-                            transaction_hash,
-                            (block_hash, index as u32),
-                            insert_closure!(),
-                        );
-                        if res.is_err() {
-                            trace!(target: "frontier-consensus", "{:?}", res);
-                        }
-                    }
+                        &transaction_hashes,
+                        pre_block,
+                    )?,
+                    None => return Err(Error::EthereumBlockMismatch.into()),
                 }
             }
-            // On importing block 1 we also map the genesis block in the auxiliary.
-            if block.header.number().clone() == One::one() {
-                let id = BlockId::Number(Zero::zero());
-                if let Ok(Some(header)) = client.header(id) {
-                    let block = self
-                        .client
-                        .runtime_api()
-                        .current_block(&id)
-                        .map_err(|_| Error::RuntimeApiCallFailed)?;
-                    let block_hash = block.unwrap().header.hash();
-                    let res = aux_schema::write_block_hash(
+            // On importing block 1 we also map the genesis block, provided `pallet_ethereum` was
+            // part of the genesis runtime.
+            let genesis = if number == One::one() {
+                client
+                    .hash(Zero::zero())
+                    .ok()
+                    .flatten()
+                    .and_then(|genesis_hash| {
+                        fetch_ethereum_block_or_warn(client.as_ref(), genesis_hash)
+                            .map(|genesis_block| (genesis_hash, genesis_block))
+                    })
+            } else {
+                None
+            };
+
+            let _write_lock = aux_schema::WRITE_LOCK.lock();
+            if let Some((genesis_hash, (block, receipts, statuses))) = genesis {
+                let res = aux_schema::write_block_mappings::<B, _, _, _>(
+                    client.as_ref(),
+                    Zero::zero(),
+                    genesis_hash,
+                    block.header.hash(),
+                    Vec::new(),
+                    |insert| client.insert_aux(insert, &[]),
+                )
+                .and_then(|res| res)
+                .and_then(|_| {
+                    aux_schema::write_ethereum_block_data(
                         client.as_ref(),
-                        block_hash,
-                        header.hash(),
-                        insert_closure!(),
-                    );
-                    if res.is_err() {
-                        trace!(target: "frontier-consensus", "{:?}", res);
-                    }
+                        &block,
+                        &receipts,
+                        &statuses,
+                    )
+                });
+                if res.is_err() {
+                    warn!(target: "frontier-consensus", "{:?}", res);
+                }
+            }
+            let res = aux_schema::write_block_mappings::<B, _, _, _>(
+                client.as_ref(),
+                number,
+                hash,
+                block_hash,
+                transaction_hashes,
+                |insert| client.insert_aux(insert, &[]),
+            )
+            .and_then(|res| res);
+            if res.is_err() {
+                warn!(target: "frontier-consensus", "{:?}", res);
+            }
+            if let Some((block, receipts, statuses)) = ethereum_block {
+                let res = aux_schema::write_ethereum_block_data(
+                    client.as_ref(),
+                    &block,
+                    &receipts,
+                    &statuses,
+                );
+                if res.is_err() {
+                    warn!(target: "frontier-consensus", "{:?}", res);
                 }
             }
         }
+        Ok(imported)
+    }
+}
 
-        self.inner
-            .import_block(block, new_cache)
-            .map_err(Into::into)
+/// Return `Pending` once so that other tasks on the executor get to run.
+pub(crate) async fn yield_now() {
+    let mut yielded = false;
+    futures::future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+fn fetch_ethereum_block_or_warn<B, C>(
+    client: &C,
+    hash: B::Hash,
+) -> Option<(ethereum::Block, Vec<ethereum::Receipt>, Vec<TransactionStatus>)>
+    where
+        B: BlockT,
+        C: ProvideRuntimeApi<B>,
+        C::Api: EthereumRuntimeRPCApi<B>,
+{
+    block_store::fetch_ethereum_block::<B, _>(client, hash).unwrap_or_else(|err| {
+        warn!(
+            target: "frontier-consensus",
+            "Failed to fetch Ethereum block {:?}: {:?}", hash, err,
+        );
+        None
+    })
+}
+
+/// Check the Ethereum block a runtime stored against the post-runtime log, and against the
+/// `PreBlock` log if the author put one in the header.
+fn check_ethereum_block(
+    ethereum_block: &ethereum::Block,
+    block_hash: H256,
+    transaction_hashes: &[H256],
+    pre_block: Option<ConsensusLog>,
+) -> Result<(), Error> {
+    let stored_hashes = ethereum_block
+        .transactions
+        .iter()
+        .map(|t| H256::from_slice(Keccak256::digest(&rlp::encode(t)).as_slice()))
+        .collect::<Vec<_>>();
+    if ethereum_block.header.hash() != block_hash || stored_hashes != transaction_hashes {
+        return Err(Error::EthereumBlockMismatch);
+    }
+    if let Some(ConsensusLog::PreBlock {
+        beneficiary,
+        extra_data,
+        mix_hash,
+    }) = pre_block
+    {
+        let header = &ethereum_block.header;
+        if header.beneficiary != beneficiary
+            || header.extra_data != extra_data
+            || header.mix_hash != mix_hash
+        {
+            return Err(Error::EthereumBlockMismatch);
+        }
     }
+    Ok(())
 }
 
-fn find_frontier_log<B: BlockT>(header: &B::Header) -> Result<ConsensusLog, Error> {
+/// Whether the runtime at `id` exposes the Ethereum runtime API, i.e. false for blocks from
+/// before `pallet_ethereum` was added to the chain. Fails when the state at `id` is not
+/// available, e.g. pruned.
+pub fn has_ethereum_api<B, C>(client: &C, id: &BlockId<B>) -> ClientResult<bool>
+    where
+        B: BlockT,
+        C: ProvideRuntimeApi<B>,
+        C::Api: EthereumRuntimeRPCApi<B>,
+{
+    client
+        .runtime_api()
+        .has_api::<dyn EthereumRuntimeRPCApi<B>>(id)
+        .map_err(|err| ClientError::Backend(format!("runtime state unavailable: {:?}", err)))
+}
+
+/// Ethereum block hash and transaction hashes of the post-runtime log.
+fn find_frontier_log<B: BlockT>(header: &B::Header) -> Result<(H256, Vec<H256>), Error> {
     let mut frontier_log: Option<_> = None;
     for log in header.digest().logs() {
         trace!(target: "frontier-consensus", "Checking log {:?}, looking for ethereum block.", log);
         let log = log.try_to::<ConsensusLog>(OpaqueDigestItemId::Consensus(&FRONTIER_ENGINE_ID));
         match (log, frontier_log.is_some()) {
-            (Some(_), true) => return Err(Error::MultiplePostRuntimeLogs),
-            (Some(log), false) => frontier_log = Some(log),
+            (Some(ConsensusLog::EndBlock { .. }), true) => {
+                return Err(Error::MultiplePostRuntimeLogs)
+            }
+            (
+                Some(ConsensusLog::EndBlock {
+                    block_hash,
+                    transaction_hashes,
+                }),
+                false,
+            ) => frontier_log = Some((block_hash, transaction_hashes)),
             _ => trace!(target: "frontier-consensus", "Ignoring digest not meant for us"),
         }
     }
 
     Ok(frontier_log.ok_or(Error::NoPostRuntimeLog)?)
-}
\ No newline at end of file
+}
+
+/// The `PreBlock` log the author put in the pre-runtime digests, if any, under the same rule as
+/// the runtime.
+fn find_pre_log<B: BlockT>(header: &B::Header) -> Result<Option<ConsensusLog>, Error> {
+    let pre_runtime_digests = header.digest().logs().iter().filter_map(|d| d.as_pre_runtime());
+    find_pre_block(pre_runtime_digests).map_err(|err| match err {
+        PreBlockError::Multiple => Error::MultiplePreRuntimeLogs,
+        PreBlockError::Invalid => Error::InvalidPreRuntimeLog,
+        PreBlockError::ExtraDataTooLarge => Error::ExtraDataTooLarge,
+    })
+}
+
+/// Pre-runtime digest an author includes in its proposals so the Ethereum header of the block
+/// carries the given beneficiary, extra data and mix hash. `PreBlockProposerFactory` adds it to
+/// every block it proposes.
+pub fn pre_block_digest<B: BlockT>(
+    beneficiary: H160,
+    extra_data: Vec<u8>,
+    mix_hash: H256,
+) -> Result<DigestItemFor<B>, Error> {
+    if extra_data.len() > MAX_EXTRA_DATA_SIZE {
+        return Err(Error::ExtraDataTooLarge);
+    }
+    Ok(DigestItemFor::<B>::PreRuntime(
+        FRONTIER_ENGINE_ID,
+        ConsensusLog::PreBlock {
+            beneficiary,
+            extra_data,
+            mix_hash,
+        }
+        .encode(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sp_core::U256;
+
+    fn ethereum_block(beneficiary: H160) -> ethereum::Block {
+        let partial_header = ethereum::PartialHeader {
+            parent_hash: H256::default(),
+            beneficiary,
+            state_root: H256::default(),
+            receipts_root: H256::default(),
+            logs_bloom: Default::default(),
+            difficulty: U256::zero(),
+            number: U256::one(),
+            gas_limit: U256::zero(),
+            gas_used: U256::zero(),
+            timestamp: 0,
+            extra_data: b"frontier".to_vec(),
+            mix_hash: H256::default(),
+            nonce: Default::default(),
+        };
+        ethereum::Block::new(partial_header, Vec::new(), Vec::new())
+    }
+
+    fn pre_block(beneficiary: H160) -> Option<ConsensusLog> {
+        Some(ConsensusLog::PreBlock {
+            beneficiary,
+            extra_data: b"frontier".to_vec(),
+            mix_hash: H256::default(),
+        })
+    }
+
+    #[test]
+    fn strict_check_accepts_matching_block() {
+        let block = ethereum_block(H160::repeat_byte(0x01));
+        let hash = block.header.hash();
+        assert!(check_ethereum_block(&block, hash, &[], None).is_ok());
+        assert!(
+            check_ethereum_block(&block, hash, &[], pre_block(H160::repeat_byte(0x01))).is_ok()
+        );
+    }
+
+    #[test]
+    fn strict_check_rejects_mismatches() {
+        let block = ethereum_block(H160::repeat_byte(0x01));
+        let hash = block.header.hash();
+        assert!(matches!(
+            check_ethereum_block(&block, H256::repeat_byte(0x02), &[], None),
+            Err(Error::EthereumBlockMismatch)
+        ));
+        assert!(matches!(
+            check_ethereum_block(&block, hash, &[H256::repeat_byte(0x03)], None),
+            Err(Error::EthereumBlockMismatch)
+        ));
+        assert!(matches!(
+            check_ethereum_block(&block, hash, &[], pre_block(H160::repeat_byte(0x04))),
+            Err(Error::EthereumBlockMismatch)
+        ));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{aux_schema, block_store::fetch_ethereum_block, find_frontier_log, has_ethereum_api};
+use ap_rpc::EthereumRuntimeRPCApi;
+use futures::StreamExt;
+use log::*;
+use sc_client_api::{backend::AuxStore, BlockchainEvents};
+use sp_api::{BlockId, ProvideRuntimeApi};
+use sp_blockchain::{Error as ClientError, HeaderBackend, Result as ClientResult};
+use sp_core::H256;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT, NumberFor, One, Zero};
+use std::{marker::PhantomData, num::NonZeroU32, sync::Arc};
+
+/// Backfills the Ethereum block and transaction hash mappings for canonical blocks whose import
+/// did not write them, e.g. after warp sync, with mapping disabled on import, or after a crash.
+pub struct MappingSyncWorker<B: BlockT, C> {
+    client: Arc<C>,
+    batch_size: NonZeroU32,
+    _marker: PhantomData<B>,
+}
+
+impl<B, C> MappingSyncWorker<B, C>
+    where
+        B: BlockT,
+        C: ProvideRuntimeApi<B> + HeaderBackend<B> + BlockchainEvents<B> + AuxStore,
+        C::Api: EthereumRuntimeRPCApi<B>,
+{
+    pub fn new(client: Arc<C>, batch_size: NonZeroU32) -> Self {
+        Self {
+            client,
+            batch_size,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Last canonical block whose mappings are known to be complete, and the best block.
+    pub fn status(&self) -> ClientResult<(NumberFor<B>, NumberFor<B>)> {
+        let synced = match aux_schema::load_mapping_sync_tip::<B, _>(self.client.as_ref())? {
+            Some(tip) => self.canonical_tip(tip)?.0,
+            None => Zero::zero(),
+        };
+        Ok((synced, self.client.info().best_number))
+    }
+
+    /// Walk back from `tip` to the first block that is still canonical.
+    fn canonical_tip(&self, tip: B::Hash) -> ClientResult<(NumberFor<B>, B::Hash)> {
+        let mut hash = tip;
+        loop {
+            let header = self
+                .client
+                .header(BlockId::Hash(hash))?
+                .ok_or(ClientError::UnknownBlock(format!("{:?}", hash)))?;
+            let number = *header.number();
+            if number.is_zero() || self.client.hash(number)? == Some(hash) {
+                return Ok((number, hash));
+            }
+            hash = *header.parent_hash();
+        }
+    }
+
+    /// Sync up to `batch_size` blocks. Returns whether more blocks can be synced right away, as
+    /// opposed to having to wait for the next import.
+    pub fn sync_batch(&self) -> ClientResult<bool> {
+        let from = match aux_schema::load_mapping_sync_tip::<B, _>(self.client.as_ref())? {
+            Some(tip) => self.canonical_tip(tip)?.0 + One::one(),
+            None => Zero::zero(),
+        };
+        let best = self.client.info().best_number;
+        let mut number = from;
+        let mut synced = 0;
+        while number <= best && synced < self.batch_size.get() {
+            let hash = match self.client.hash(number)? {
+                Some(hash) => hash,
+                None => break,
+            };
+            self.sync_block(number, hash)?;
+            aux_schema::write_mapping_sync_tip::<B, _>(self.client.as_ref(), hash)?;
+            number = number + One::one();
+            synced += 1;
+        }
+        if synced > 0 {
+            info!(
+                target: "frontier-consensus",
+                "Ethereum mapping sync at #{} (best #{})", number - One::one(), best,
+            );
+        }
+        Ok(synced == self.batch_size.get() && number <= best)
+    }
+
+    /// Ethereum block hash and transaction hashes of the block `hash` at height `number`.
+    fn block_mapping(
+        &self,
+        number: NumberFor<B>,
+        hash: B::Hash,
+    ) -> ClientResult<Option<(H256, Vec<H256>)>> {
+        let client = self.client.as_ref();
+        if number.is_zero() {
+            // The genesis block carries no digest, its Ethereum block only exists in state, which
+            // may have been pruned by now.
+            match has_ethereum_api(client, &BlockId::Hash(hash)) {
+                Ok(true) => (),
+                Ok(false) => return Ok(None),
+                Err(err) => {
+                    warn!(
+                        target: "frontier-consensus",
+                        "Cannot map the Ethereum genesis block: {:?}", err,
+                    );
+                    return Ok(None);
+                }
+            }
+            return Ok(client
+                .runtime_api()
+                .current_block(&BlockId::Hash(hash))
+                .map_err(|err| ClientError::Backend(format!("{:?}", err)))?
+                .map(|block| (block.header.hash(), Vec::new())));
+        }
+        let header = client
+            .header(BlockId::Hash(hash))?
+            .ok_or(ClientError::UnknownBlock(format!("{:?}", hash)))?;
+        Ok(find_frontier_log::<B>(&header).ok())
+    }
+
+    fn sync_block(&self, number: NumberFor<B>, hash: B::Hash) -> ClientResult<()> {
+        let client = self.client.as_ref();
+        let (ethereum_hash, transaction_hashes) = match self.block_mapping(number, hash)? {
+            Some(mapping) => mapping,
+            None => return Ok(()),
+        };
+        let ethereum_block = if aux_schema::load_ethereum_block(client, ethereum_hash)?.is_none() {
+            // Pruned state is fine, RPC then only serves what the mappings point to.
+            fetch_ethereum_block::<B, _>(client, hash).unwrap_or(None)
+        } else {
+            None
+        };
+
+        {
+            let _write_lock = aux_schema::WRITE_LOCK.lock();
+            let res = aux_schema::write_block_mappings::<B, _, _, _>(
+                client,
+                number,
+                hash,
+                ethereum_hash,
+                transaction_hashes.clone(),
+                |insert| client.insert_aux(insert, &[]),
+            )
+            .and_then(|res| res);
+            if let Err(err) = res {
+                warn!(
+                    target: "frontier-consensus",
+                    "Repairing Ethereum mappings of #{}: {:?}", number, err,
+                );
+                aux_schema::repair_block_mappings::<B, _>(
+                    client,
+                    number,
+                    hash,
+                    ethereum_hash,
+                    transaction_hashes,
+                )?;
+            }
+            // Heights that were already compacted get no further finality pass.
+            let compacted = aux_schema::load_mapping_compacted::<B, _>(client)?;
+            if compacted.map_or(false, |compacted| number <= compacted) {
+                aux_schema::compact_block_mappings::<B, _>(client, number, Some(hash))?;
+            }
+            if let Some((block, receipts, statuses)) = ethereum_block {
+                aux_schema::write_ethereum_block_data(client, &block, &receipts, &statuses)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuild the mappings of the canonical blocks `from..=to` from their headers, replacing
+    /// whatever is stored for them. Returns the number of blocks repaired.
+    pub fn repair(&self, from: NumberFor<B>, to: NumberFor<B>) -> ClientResult<u32> {
+        let client = self.client.as_ref();
+        let to = std::cmp::min(to, client.info().best_number);
+        let mut number = from;
+        let mut repaired = 0;
+        while number <= to {
+            if let Some(hash) = client.hash(number)? {
+                if let Some((ethereum_hash, transaction_hashes)) =
+                    self.block_mapping(number, hash)?
+                {
+                    let _write_lock = aux_schema::WRITE_LOCK.lock();
+                    aux_schema::repair_block_mappings::<B, _>(
+                        client,
+                        number,
+                        hash,
+                        ethereum_hash,
+                        transaction_hashes,
+                    )?;
+                    let compacted = aux_schema::load_mapping_compacted::<B, _>(client)?;
+                    if compacted.map_or(false, |compacted| number <= compacted) {
+                        aux_schema::compact_block_mappings::<B, _>(client, number, Some(hash))?;
+                    }
+                    repaired += 1;
+                }
+            }
+            number = number + One::one();
+        }
+        info!(
+            target: "frontier-consensus",
+            "Repaired Ethereum mappings of {} blocks in #{}..=#{}", repaired, from, to,
+        );
+        Ok(repaired)
+    }
+
+    /// Sync to the best block, then keep up as new blocks are imported.
+    ///
+    /// Batches make blocking database and runtime calls, so spawn this as a blocking task. It
+    /// still yields between batches so that it does not starve a shared executor.
+    pub async fn run(self) {
+        let mut notifications = self.client.import_notification_stream();
+        loop {
+            loop {
+                match self.sync_batch() {
+                    Ok(true) => crate::yield_now().await,
+                    Ok(false) => break,
+                    Err(err) => {
+                        warn!(
+                            target: "frontier-consensus",
+                            "Ethereum mapping sync failed: {:?}", err,
+                        );
+                        break;
+                    }
+                }
+            }
+            if notifications.next().await.is_none() {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use crate::{pre_block_digest, Error};
+use futures::{future::BoxFuture, FutureExt, TryFutureExt};
+use sp_consensus::{Environment, Proposer, RecordProof};
+use sp_core::{H160, H256};
+use sp_inherents::InherentData;
+use sp_runtime::traits::{Block as BlockT, DigestFor, DigestItemFor};
+use std::time::Duration;
+
+/// Wraps the proposer factory of the node's authoring task, so that every block it proposes
+/// carries a `PreBlock` digest with the given Ethereum header fields.
+pub struct PreBlockProposerFactory<B: BlockT, E> {
+    inner: E,
+    digest: DigestItemFor<B>,
+}
+
+impl<B: BlockT, E> PreBlockProposerFactory<B, E> {
+    pub fn new(
+        inner: E,
+        beneficiary: H160,
+        extra_data: Vec<u8>,
+        mix_hash: H256,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            inner,
+            digest: pre_block_digest::<B>(beneficiary, extra_data, mix_hash)?,
+        })
+    }
+}
+
+impl<B, E> Environment<B> for PreBlockProposerFactory<B, E>
+    where
+        B: BlockT,
+        E: Environment<B>,
+{
+    type Proposer = PreBlockProposer<B, E::Proposer>;
+    type CreateProposer = BoxFuture<'static, Result<Self::Proposer, Self::Error>>;
+    type Error = E::Error;
+
+    fn init(&mut self, parent_header: &B::Header) -> Self::CreateProposer {
+        let digest = self.digest.clone();
+        self.inner
+            .init(parent_header)
+            .map_ok(move |inner| PreBlockProposer { inner, digest })
+            .boxed()
+    }
+}
+
+pub struct PreBlockProposer<B: BlockT, P> {
+    inner: P,
+    digest: DigestItemFor<B>,
+}
+
+impl<B, P> Proposer<B> for PreBlockProposer<B, P>
+    where
+        B: BlockT,
+        P: Proposer<B>,
+{
+    type Error = P::Error;
+    type Transaction = P::Transaction;
+    type Proposal = P::Proposal;
+
+    fn propose(
+        self,
+        inherent_data: InherentData,
+        mut inherent_digests: DigestFor<B>,
+        max_duration: Duration,
+        record_proof: RecordProof,
+    ) -> Self::Proposal {
+        inherent_digests.push(self.digest);
+        self.inner
+            .propose(inherent_data, inherent_digests, max_duration, record_proof)
+    }
+}
@@ -1,10 +1,12 @@
 use ethereum_types::{H160, H256, H64, U256, U64};
 use jsonrpc_core::{BoxFuture, Result};
 use jsonrpc_derive::rpc;
+use std::collections::BTreeMap;
 
 use crate::types::{
-    BlockNumber, Bytes, CallRequest, Filter, FilterChanges, Index, Log, Receipt, RichBlock,
-    SyncStatus, Transaction, TransactionRequest, Work,
+    BlockNumber, BlockOverrides, Bytes, CallRequest, CallStateOverride, Filter, FilterChanges,
+    Index, Log, Receipt, RichBlock, SimulatePayload, SimulatedBlock, SyncStatus, Transaction,
+    TransactionRequest, TypedData, Work,
 };
 pub use rpc_impl_EthApi::gen_server::EthApi as EthApiServer;
 pub use rpc_impl_EthFilterApi::gen_server::EthFilterApi as EthFilterApiServer;
@@ -74,8 +76,26 @@ pub trait EthApi {
     #[rpc(name = "eth_sendRawTransaction")]
     fn send_raw_transaction(&self, _: Bytes) -> BoxFuture<H256>;
 
+    #[rpc(name = "eth_sign")]
+    fn sign(&self, _: H160, _: Bytes) -> Result<Bytes>;
+
+    #[rpc(name = "eth_signTypedData_v4")]
+    fn sign_typed_data(&self, _: H160, _: TypedData) -> Result<Bytes>;
+
+    #[rpc(name = "eth_signTransaction")]
+    fn sign_transaction(&self, _: TransactionRequest) -> Result<Bytes>;
+
     #[rpc(name = "eth_call")]
-    fn call(&self, _: CallRequest, _: Option<BlockNumber>) -> Result<Bytes>;
+    fn call(
+        &self,
+        _: CallRequest,
+        _: Option<BlockNumber>,
+        _: Option<BTreeMap<H160, CallStateOverride>>,
+        _: Option<BlockOverrides>,
+    ) -> Result<Bytes>;
+
+    #[rpc(name = "eth_simulateV1")]
+    fn simulate(&self, _: SimulatePayload, _: Option<BlockNumber>) -> Result<Vec<SimulatedBlock>>;
 
     #[rpc(name = "eth_estimateGas")]
     fn estimate_gas(&self, _: CallRequest, _: Option<BlockNumber>) -> Result<U256>;
@@ -0,0 +1,32 @@
+use ethereum_types::{H160, H256};
+use jsonrpc_core::{BoxFuture, Result};
+use jsonrpc_derive::rpc;
+
+use crate::types::{Bytes, TransactionRequest};
+
+pub use rpc_impl_PersonalApi::gen_server::PersonalApi as PersonalApiServer;
+
+#[rpc(server)]
+pub trait PersonalApi {
+    #[rpc(name = "personal_listAccounts")]
+    fn list_accounts(&self) -> Result<Vec<H160>>;
+
+    #[rpc(name = "personal_newAccount")]
+    fn new_account(&self, _: String) -> Result<H160>;
+
+    #[rpc(name = "personal_importRawKey")]
+    fn import_raw_key(&self, _: String, _: String) -> Result<H160>;
+
+    /// Unlocks an account for the given number of seconds (300 by default, 0 for indefinitely).
+    #[rpc(name = "personal_unlockAccount")]
+    fn unlock_account(&self, _: H160, _: String, _: Option<u64>) -> Result<bool>;
+
+    #[rpc(name = "personal_lockAccount")]
+    fn lock_account(&self, _: H160) -> Result<bool>;
+
+    #[rpc(name = "personal_sendTransaction")]
+    fn send_transaction(&self, _: TransactionRequest, _: String) -> BoxFuture<H256>;
+
+    #[rpc(name = "personal_sign")]
+    fn sign(&self, _: Bytes, _: H160, _: String) -> Result<Bytes>;
+}
@@ -0,0 +1,18 @@
+use jsonrpc_core::Result;
+use jsonrpc_derive::rpc;
+
+use crate::types::{TxPoolContent, TxPoolInspect, TxPoolStatus};
+
+pub use rpc_impl_TxPoolApi::gen_server::TxPoolApi as TxPoolApiServer;
+
+#[rpc(server)]
+pub trait TxPoolApi {
+    #[rpc(name = "txpool_status")]
+    fn status(&self) -> Result<TxPoolStatus>;
+
+    #[rpc(name = "txpool_content")]
+    fn content(&self) -> Result<TxPoolContent>;
+
+    #[rpc(name = "txpool_inspect")]
+    fn inspect(&self) -> Result<TxPoolInspect>;
+}
@@ -1,5 +1,5 @@
 use crate::types::Bytes;
-use ethereum_types::{H160, U256};
+use ethereum_types::{H160, H256, U256};
 use serde::Deserialize;
 
 #[derive(Debug, Default, PartialEq, Deserialize, Clone)]
@@ -13,4 +13,6 @@ pub struct CallRequest {
     pub value: Option<U256>,
     pub data: Option<Bytes>,
     pub nonce: Option<U256>,
+    /// Deploy with CREATE2 semantics using this salt. Only for requests without `to`.
+    pub salt: Option<H256>,
 }
\ No newline at end of file
@@ -7,9 +7,13 @@ mod filter;
 mod index;
 mod log;
 mod receipt;
+mod simulate;
+mod state_override;
 mod sync;
 mod transaction;
 mod transaction_request;
+mod txpool;
+mod typed_data;
 mod work;
 
 pub mod pubsub;
@@ -28,6 +32,8 @@ pub use self::filter::{
 pub use self::index::Index;
 pub use self::log::Log;
 pub use self::receipt::Receipt;
+pub use self::simulate::{SimulateBlock, SimulatePayload, SimulatedBlock, SimulatedCall};
+pub use self::state_override::{BlockOverrides, CallStateOverride};
 pub use self::sync::{
     ChainStatus, EthProtocolInfo, PeerInfo, PeerNetworkInfo, PeerProtocolsInfo, Peers,
     PipProtocolInfo, SyncInfo, SyncStatus, TransactionStats,
@@ -37,4 +43,9 @@ pub use self::transaction::{
     Transaction,
 };
 pub use self::transaction_request::TransactionRequest;
+pub use self::txpool::{
+    Summary, TransactionMap, TransactionNonce, TxPoolContent, TxPoolInspect, TxPoolResult,
+    TxPoolStatus,
+};
+pub use self::typed_data::{TypedData, TypedDataField};
 pub use self::work::Work;
\ No newline at end of file
@@ -0,0 +1,40 @@
+use crate::types::{BlockOverrides, Bytes, CallRequest, CallStateOverride, Log};
+use ethereum_types::{H160, U256, U64};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default, PartialEq, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatePayload {
+    pub block_state_calls: Vec<SimulateBlock>,
+    #[serde(default)]
+    pub trace_transfers: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulateBlock {
+    pub block_overrides: Option<BlockOverrides>,
+    pub state_overrides: Option<BTreeMap<H160, CallStateOverride>>,
+    #[serde(default)]
+    pub calls: Vec<CallRequest>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedBlock {
+    pub calls: Vec<SimulatedCall>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedCall {
+    pub return_data: Bytes,
+    pub logs: Vec<Log>,
+    pub gas_used: U256,
+    pub status: U64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<jsonrpc_core::Error>,
+}
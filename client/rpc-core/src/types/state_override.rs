@@ -0,0 +1,25 @@
+use crate::types::Bytes;
+use ethereum_types::{H160, H256, U256};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default, PartialEq, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct CallStateOverride {
+    pub balance: Option<U256>,
+    pub nonce: Option<U256>,
+    pub code: Option<Bytes>,
+    pub state: Option<BTreeMap<H256, H256>>,
+    pub state_diff: Option<BTreeMap<H256, H256>>,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockOverrides {
+    pub number: Option<U256>,
+    pub time: Option<U256>,
+    pub coinbase: Option<H160>,
+    pub gas_limit: Option<U256>,
+}
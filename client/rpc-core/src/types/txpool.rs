@@ -0,0 +1,78 @@
+use crate::types::Transaction;
+use ethereum_types::{H160, U256};
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// Transactions grouped by sender and nonce.
+pub type TransactionMap<T> = BTreeMap<H160, BTreeMap<TransactionNonce, T>>;
+
+/// Nonce key of `TransactionMap`, serialized as a decimal string like geth does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TransactionNonce(pub U256);
+
+impl Serialize for TransactionNonce {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        serializer.serialize_str(&self.0.to_string())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct TxPoolResult<T: Serialize> {
+    pub pending: T,
+    pub queued: T,
+}
+
+pub type TxPoolStatus = TxPoolResult<U256>;
+pub type TxPoolContent = TxPoolResult<TransactionMap<Transaction>>;
+pub type TxPoolInspect = TxPoolResult<TransactionMap<Summary>>;
+
+/// One-line transaction description used by `txpool_inspect`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub to: Option<H160>,
+    pub value: U256,
+    pub gas: U256,
+    pub gas_price: U256,
+}
+
+impl Serialize for Summary {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        let to = match self.to {
+            Some(to) => format!("0x{:x}", to),
+            None => "contract creation".to_string(),
+        };
+        serializer.serialize_str(&format!(
+            "{}: {} wei + {} gas × {} wei",
+            to, self.value, self.gas, self.gas_price
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_nonces_as_decimal_strings() {
+        let mut map: TransactionMap<Summary> = TransactionMap::new();
+        map.entry(H160::repeat_byte(0x01)).or_default().insert(
+            TransactionNonce(U256::from(26)),
+            Summary {
+                to: None,
+                value: U256::from(1),
+                gas: U256::from(21000),
+                gas_price: U256::from(2),
+            },
+        );
+        assert_eq!(
+            serde_json::to_string(&map).unwrap(),
+            r#"{"0x0101010101010101010101010101010101010101":{"26":"contract creation: 1 wei + 21000 gas × 2 wei"}}"#
+        );
+    }
+}
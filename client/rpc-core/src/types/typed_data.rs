@@ -0,0 +1,20 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// EIP-712 typed structured data, as accepted by `eth_signTypedData_v4`.
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData {
+    pub types: BTreeMap<String, Vec<TypedDataField>>,
+    pub primary_type: String,
+    pub domain: Value,
+    pub message: Value,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub struct TypedDataField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+}
@@ -0,0 +1,314 @@
+use crate::internal_err;
+use ac_rpc_core::types::TypedData;
+use ethereum_types::{H160, H256, U256};
+use jsonrpc_core::Error;
+use rustc_hex::FromHex;
+use serde_json::Value;
+use sha3::{Digest, Keccak256};
+use std::collections::BTreeSet;
+
+fn keccak(data: &[u8]) -> H256 {
+    H256::from_slice(Keccak256::digest(data).as_slice())
+}
+
+fn decode_hex(value: &Value) -> Result<Vec<u8>, Error> {
+    value
+        .as_str()
+        .ok_or(internal_err("expected hex string in typed data"))?
+        .trim_start_matches("0x")
+        .from_hex()
+        .map_err(|_| internal_err("invalid hex in typed data"))
+}
+
+fn decode_integer(value: &Value) -> Result<(bool, U256), Error> {
+    let invalid = || internal_err(format!("invalid integer in typed data: {}", value));
+    match value {
+        Value::Number(number) => match (number.as_u64(), number.as_i64()) {
+            (Some(number), _) => Ok((false, U256::from(number))),
+            (None, Some(number)) => Ok((true, U256::from((number as i128).abs() as u128))),
+            _ => Err(invalid()),
+        },
+        Value::String(number) => {
+            let (negative, number) = match number.strip_prefix('-') {
+                Some(number) => (true, number),
+                None => (false, number.as_str()),
+            };
+            let number = match number.strip_prefix("0x") {
+                Some(hex) => U256::from_str_radix(hex, 16).map_err(|_| invalid())?,
+                None => U256::from_dec_str(number).map_err(|_| invalid())?,
+            };
+            Ok((negative, number))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn base_type(ty: &str) -> &str {
+    ty.split('[').next().unwrap_or(ty)
+}
+
+fn dependencies<'a>(typed_data: &'a TypedData, ty: &'a str, found: &mut BTreeSet<&'a str>) {
+    if found.contains(ty) {
+        return;
+    }
+    if let Some((name, fields)) = typed_data.types.get_key_value(ty) {
+        found.insert(name);
+        for field in fields {
+            dependencies(typed_data, base_type(&field.type_), found);
+        }
+    }
+}
+
+fn encode_type(typed_data: &TypedData, primary_type: &str) -> String {
+    let mut deps = BTreeSet::new();
+    dependencies(typed_data, primary_type, &mut deps);
+    deps.remove(primary_type);
+    let mut encoded = String::new();
+    for ty in std::iter::once(primary_type).chain(deps.into_iter()) {
+        let fields = typed_data.types[ty]
+            .iter()
+            .map(|field| format!("{} {}", field.type_, field.name))
+            .collect::<Vec<_>>();
+        encoded.push_str(&format!("{}({})", ty, fields.join(",")));
+    }
+    encoded
+}
+
+fn hash_struct(typed_data: &TypedData, ty: &str, data: &Value) -> Result<H256, Error> {
+    let fields = typed_data
+        .types
+        .get(ty)
+        .ok_or(internal_err(format!("unknown type in typed data: {}", ty)))?;
+    let mut encoded = keccak(encode_type(typed_data, ty).as_bytes())
+        .as_bytes()
+        .to_vec();
+    for field in fields {
+        let value = data.get(&field.name).unwrap_or(&Value::Null);
+        encoded.extend_from_slice(encode_value(typed_data, &field.type_, value)?.as_bytes());
+    }
+    Ok(keccak(&encoded))
+}
+
+fn encode_value(typed_data: &TypedData, ty: &str, value: &Value) -> Result<H256, Error> {
+    if let Some(start) = ty.rfind('[') {
+        if start == 0 || !ty.ends_with(']') {
+            return Err(internal_err(format!("invalid array type {}", ty)));
+        }
+        let items = value
+            .as_array()
+            .ok_or(internal_err(format!("expected array for type {}", ty)))?;
+        let length = &ty[start + 1..ty.len() - 1];
+        if !length.is_empty() && length.parse::<usize>().ok() != Some(items.len()) {
+            return Err(internal_err(format!("invalid array length for type {}", ty)));
+        }
+        let mut encoded = Vec::new();
+        for item in items {
+            encoded.extend_from_slice(encode_value(typed_data, &ty[..start], item)?.as_bytes());
+        }
+        return Ok(keccak(&encoded));
+    }
+    if typed_data.types.contains_key(ty) {
+        return hash_struct(typed_data, ty, value);
+    }
+    match ty {
+        "string" => Ok(keccak(
+            value
+                .as_str()
+                .ok_or(internal_err("expected string in typed data"))?
+                .as_bytes(),
+        )),
+        "bytes" => Ok(keccak(&decode_hex(value)?)),
+        "bool" => match value {
+            Value::Bool(b) => Ok(H256::from_low_u64_be(*b as u64)),
+            _ => Err(internal_err("expected boolean in typed data")),
+        },
+        "address" => {
+            let address = decode_hex(value)?;
+            if address.len() != 20 {
+                return Err(internal_err("invalid address in typed data"));
+            }
+            Ok(H256::from(H160::from_slice(&address)))
+        }
+        _ if ty.starts_with("bytes") => {
+            let bytes = decode_hex(value)?;
+            match ty[5..].parse::<usize>() {
+                Ok(size) if size >= 1 && size <= 32 && bytes.len() <= size => {
+                    let mut res = H256::zero();
+                    res.as_bytes_mut()[..bytes.len()].copy_from_slice(&bytes);
+                    Ok(res)
+                }
+                _ => Err(internal_err(format!("invalid value for type {}", ty))),
+            }
+        }
+        _ if ty.starts_with("uint") || ty.starts_with("int") => {
+            let signed = ty.starts_with("int");
+            let bits = match &ty[if signed { 3 } else { 4 }..] {
+                "" => 256,
+                size => match size.parse::<usize>() {
+                    Ok(bits) if bits >= 8 && bits <= 256 && bits % 8 == 0 => bits,
+                    _ => return Err(internal_err(format!("unknown type in typed data: {}", ty))),
+                },
+            };
+            let (negative, number) = decode_integer(value)?;
+            if negative && !signed {
+                return Err(internal_err(format!("negative value for type {}", ty)));
+            }
+            // intN holds -2^(N-1) up to 2^(N-1) - 1, uintN up to 2^N - 1.
+            let in_range = if signed {
+                let limit = U256::one() << (bits - 1);
+                if negative {
+                    number <= limit
+                } else {
+                    number < limit
+                }
+            } else {
+                bits == 256 || number >> bits == U256::zero()
+            };
+            if !in_range {
+                return Err(internal_err(format!("value out of range for type {}", ty)));
+            }
+            let number = if negative {
+                (!number).overflowing_add(U256::one()).0
+            } else {
+                number
+            };
+            let mut res = [0u8; 32];
+            number.to_big_endian(&mut res);
+            Ok(H256::from(res))
+        }
+        _ => Err(internal_err(format!("unknown type in typed data: {}", ty))),
+    }
+}
+
+/// Signing hash of EIP-712 typed data: `keccak256("\x19\x01" ‖ domainSeparator ‖ hashStruct(message))`.
+pub fn hash_typed_data(typed_data: &TypedData) -> Result<H256, Error> {
+    let mut encoded = vec![0x19, 0x01];
+    encoded.extend_from_slice(
+        hash_struct(typed_data, "EIP712Domain", &typed_data.domain)?.as_bytes(),
+    );
+    if typed_data.primary_type != "EIP712Domain" {
+        encoded.extend_from_slice(
+            hash_struct(typed_data, &typed_data.primary_type, &typed_data.message)?.as_bytes(),
+        );
+    }
+    Ok(keccak(&encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The "Mail" example of the EIP-712 specification.
+    fn mail() -> TypedData {
+        serde_json::from_str(
+            r#"{
+                "types": {
+                    "EIP712Domain": [
+                        { "name": "name", "type": "string" },
+                        { "name": "version", "type": "string" },
+                        { "name": "chainId", "type": "uint256" },
+                        { "name": "verifyingContract", "type": "address" }
+                    ],
+                    "Person": [
+                        { "name": "name", "type": "string" },
+                        { "name": "wallet", "type": "address" }
+                    ],
+                    "Mail": [
+                        { "name": "from", "type": "Person" },
+                        { "name": "to", "type": "Person" },
+                        { "name": "contents", "type": "string" }
+                    ]
+                },
+                "primaryType": "Mail",
+                "domain": {
+                    "name": "Ether Mail",
+                    "version": "1",
+                    "chainId": 1,
+                    "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+                },
+                "message": {
+                    "from": {
+                        "name": "Cow",
+                        "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"
+                    },
+                    "to": {
+                        "name": "Bob",
+                        "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"
+                    },
+                    "contents": "Hello, Bob!"
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn h256(hex: &str) -> H256 {
+        H256::from_slice(&hex.from_hex::<Vec<u8>>().unwrap())
+    }
+
+    #[test]
+    fn hashes_mail_example() {
+        let typed_data = mail();
+        assert_eq!(
+            encode_type(&typed_data, "Mail"),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        assert_eq!(
+            keccak(encode_type(&typed_data, "Mail").as_bytes()),
+            h256("a0cedeb2dc280ba39b857546d74f5549c3a1d7bdc2dd96bf881f76108e23dac2")
+        );
+        assert_eq!(
+            hash_struct(&typed_data, "Mail", &typed_data.message).unwrap(),
+            h256("c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e")
+        );
+        assert_eq!(
+            hash_struct(&typed_data, "EIP712Domain", &typed_data.domain).unwrap(),
+            h256("f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f")
+        );
+        assert_eq!(
+            hash_typed_data(&typed_data).unwrap(),
+            h256("be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2")
+        );
+    }
+
+    #[test]
+    fn range_checks_integers() {
+        let typed_data = mail();
+        let encode = |ty: &str, value: Value| encode_value(&typed_data, ty, &value);
+
+        assert!(encode("uint8", Value::from(255)).is_ok());
+        assert!(encode("uint8", Value::from(256)).is_err());
+        assert!(encode("uint8", Value::from(-1)).is_err());
+        assert!(encode("int8", Value::from(127)).is_ok());
+        assert!(encode("int8", Value::from(128)).is_err());
+        assert!(encode("int8", Value::from(-128)).is_ok());
+        assert!(encode("int8", Value::from(-129)).is_err());
+        assert!(encode("uint7", Value::from(1)).is_err());
+        assert!(encode("uint264", Value::from(1)).is_err());
+        assert_eq!(
+            encode("int16", Value::from("-0x1")).unwrap(),
+            H256::repeat_byte(0xff)
+        );
+        assert!(encode(
+            "uint256",
+            Value::from("0xffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_array_types() {
+        let typed_data = mail();
+        let encode = |ty: &str| encode_value(&typed_data, ty, &serde_json::json!([1]));
+
+        assert!(encode("uint256[]").is_ok());
+        assert!(encode("uint256[1]").is_ok());
+        assert!(encode("uint256[").is_err());
+        assert!(encode("uint256[1").is_err());
+        assert!(encode("uint256]").is_err());
+        assert!(encode("uint256][").is_err());
+        assert!(encode("[]").is_err());
+        assert!(encode("[").is_err());
+        assert!(encode("]").is_err());
+    }
+}
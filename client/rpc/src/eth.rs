@@ -1,56 +1,89 @@
-use crate::{error_on_execution_failure, internal_err, public_key, EthSigner};
-use codec::Decode;
+use crate::{
+    error_on_execution_failure, internal_err, overrides::OverrideHandle, public_key, EthSigner,
+};
 use ethereum::{Block as EthereumBlock, Transaction as EthereumTransaction};
 use ethereum_types::{H160, H256, H512, H64, U256, U64};
 use ac_rpc_core::types::{
-    Block, BlockNumber, BlockTransactions, Bytes, CallRequest, Filter, FilterChanges, FilterPool,
-    FilterPoolItem, FilterType, FilteredParams, Index, Log, PendingTransaction,
-    PendingTransactions, Receipt, Rich, RichBlock, SyncInfo, SyncStatus, Transaction,
-    TransactionRequest, Work,
+    Block, BlockNumber, BlockOverrides, BlockTransactions, Bytes, CallRequest, CallStateOverride,
+    Filter, FilterChanges, FilterPool, FilterPoolItem, FilterType, FilteredParams, Index, Log,
+    PendingTransaction, PendingTransactions, Receipt, Rich, RichBlock, SimulatePayload,
+    SimulatedBlock, SimulatedCall, SyncInfo, SyncStatus, Transaction, TransactionRequest, TypedData,
+    Work,
 };
 use ac_rpc_core::{
     EthApi as EthApiT, EthFilterApi as EthFilterApiT, NetApi as NetApiT, Web3Api as Web3ApiT,
 };
+use ap_evm::{BlockOverride, CallOrCreateInfo, SimulateBlock, SimulateCall, StateOverride};
 use ap_rpc::{ConvertTransaction, EthereumRuntimeRPCApi, TransactionStatus};
 use futures::future::TryFutureExt;
+use log::warn;
 use jsonrpc_core::{
     futures::future::{self, Future},
     BoxFuture, Result,
 };
 use sc_client_api::backend::{AuxStore, Backend, StateBackend, StorageProvider};
 use sc_network::{ExHashT, NetworkService};
-use sc_transaction_graph::{ChainApi, Pool};
+use sc_transaction_graph::{ChainApi, ExtrinsicHash, Pool};
 use sha3::{Digest, Keccak256};
-use sp_api::{BlockId, Core, HeaderT, ProvideRuntimeApi};
+use sp_api::{ApiExt, BlockId, Core, HeaderT, ProvideRuntimeApi};
 use sp_blockchain::{Error as BlockChainError, HeaderBackend, HeaderMetadata};
-use sp_io::hashing::{blake2_128, twox_128};
 use sp_runtime::{
     traits::{BlakeTwo256, Block as BlockT, One, Saturating, UniqueSaturatedInto, Zero},
-    transaction_validity::TransactionSource,
+    transaction_validity::{InvalidTransaction, TransactionSource},
+    DispatchError,
+};
+use pallet_ethereum::TransactionValidationError;
+use sp_transaction_pool::{
+    error::{Error as PoolError, IntoPoolError},
+    InPoolTransaction, TransactionPool,
 };
-use sp_storage::StorageKey;
-use sp_transaction_pool::{InPoolTransaction, TransactionPool};
 use std::collections::BTreeMap;
-use std::{marker::PhantomData, sync::Arc};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{marker::PhantomData, sync::mpsc, sync::Arc, sync::Mutex, thread, time::Duration};
 
 use codec::{self, Encode};
 pub use ac_rpc_core::{EthApiServer, EthFilterApiServer, NetApiServer, Web3ApiServer};
 
+/// Minimum gas price increase, in percent, for a transaction to replace a pooled one with the same
+/// sender and nonce, as in geth.
+pub const PRICE_BUMP_PERCENT: u64 = 10;
+
+/// Gas cap for `eth_call`, `eth_estimateGas` and `eth_simulateV1` when none is configured.
+pub const DEFAULT_RPC_GAS_CAP: u64 = 50_000_000;
+
+/// Maximum number of blocks in one `eth_simulateV1` request, as in geth.
+const MAX_SIMULATE_BLOCKS: usize = 256;
+
+/// Maximum number of calls in one block of an `eth_simulateV1` request.
+const MAX_SIMULATE_CALLS_PER_BLOCK: usize = 1000;
+
+/// Maximum number of runtime executions for RPC requests running at the same time when none is
+/// configured, including the ones that already timed out but are still running in the background.
+/// Only applies with an execution timeout.
+pub const DEFAULT_MAX_CONCURRENT_EXECUTIONS: usize = 16;
+
 pub struct EthApi<B: BlockT, C, P, CT, BE, H: ExHashT, A: ChainApi> {
     pool: Arc<P>,
     graph: Arc<Pool<A>>,
     client: Arc<C>,
+    overrides: Arc<OverrideHandle<B>>,
     convert_transaction: CT,
     network: Arc<NetworkService<B, H>>,
     is_authority: bool,
     signers: Vec<Box<dyn EthSigner>>,
     pending_transactions: PendingTransactions,
+    gas_cap: U256,
+    execute_timeout: Option<Duration>,
+    max_concurrent_executions: usize,
+    running_executions: Arc<AtomicUsize>,
+    local_nonces: LocalNonces<ExtrinsicHash<A>>,
     _marker: PhantomData<(B, BE)>,
 }
 
 impl<B: BlockT, C, P, CT, BE, H: ExHashT, A: ChainApi> EthApi<B, C, P, CT, BE, H, A> {
     pub fn new(
         client: Arc<C>,
+        overrides: Arc<OverrideHandle<B>>,
         pool: Arc<P>,
         graph: Arc<Pool<A>>,
         convert_transaction: CT,
@@ -58,9 +91,14 @@ impl<B: BlockT, C, P, CT, BE, H: ExHashT, A: ChainApi> EthApi<B, C, P, CT, BE, H
         pending_transactions: PendingTransactions,
         signers: Vec<Box<dyn EthSigner>>,
         is_authority: bool,
+        gas_cap: Option<U256>,
+        execute_timeout: Option<Duration>,
+        max_concurrent_executions: Option<usize>,
+        local_nonces: LocalNonces<ExtrinsicHash<A>>,
     ) -> Self {
         Self {
             client,
+            overrides,
             pool,
             graph,
             convert_transaction,
@@ -68,11 +106,151 @@ impl<B: BlockT, C, P, CT, BE, H: ExHashT, A: ChainApi> EthApi<B, C, P, CT, BE, H
             is_authority,
             signers,
             pending_transactions,
+            gas_cap: gas_cap.unwrap_or_else(|| U256::from(DEFAULT_RPC_GAS_CAP)),
+            execute_timeout,
+            max_concurrent_executions: max_concurrent_executions
+                .unwrap_or(DEFAULT_MAX_CONCURRENT_EXECUTIONS),
+            running_executions: Arc::new(AtomicUsize::new(0)),
+            local_nonces,
             _marker: PhantomData,
         }
     }
 }
 
+/// Nonces handed out to locally signed transactions that may not have reached the pool yet, so
+/// that concurrent requests from one account do not reuse a nonce. Each reserved nonce maps to the
+/// pool hash of its transaction once the pool accepted it.
+///
+/// Every `EthApi` that signs transactions, including the one behind `PersonalApi`, must be given
+/// a clone of the same tracker.
+pub struct LocalNonces<H>(Arc<Mutex<BTreeMap<H160, BTreeMap<U256, Option<H>>>>>);
+
+impl<H> Clone for LocalNonces<H> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<H> Default for LocalNonces<H> {
+    fn default() -> Self {
+        Self(Default::default())
+    }
+}
+
+impl<H: Clone> LocalNonces<H> {
+    /// Reserve the lowest nonce of `address` that is at least the pool nonce and not reserved
+    /// yet. Reservations below the pool nonce, and committed ones whose transaction left the pool,
+    /// are forgotten first.
+    fn reserve<F, G>(
+        &self,
+        address: H160,
+        pool_nonce: F,
+        in_pool: G,
+    ) -> Result<NonceReservation<H>>
+        where
+            F: FnOnce() -> Result<U256>,
+            G: Fn(&H) -> bool,
+    {
+        let mut nonces = self
+            .0
+            .lock()
+            .map_err(|_| internal_err("nonce tracker is not available"))?;
+        let pool_nonce = pool_nonce()?;
+        let reserved = nonces.entry(address).or_default();
+        *reserved = reserved.split_off(&pool_nonce);
+        reserved.retain(|_, hash| match hash {
+            Some(hash) => in_pool(hash),
+            None => true,
+        });
+        let mut nonce = pool_nonce;
+        while reserved.contains_key(&nonce) {
+            nonce = nonce.saturating_add(U256::one());
+        }
+        reserved.insert(nonce, None);
+        Ok(NonceReservation {
+            nonces: self.clone(),
+            address,
+            nonce,
+            committed: false,
+        })
+    }
+
+    fn commit(&self, address: &H160, nonce: &U256, hash: H) {
+        if let Ok(mut nonces) = self.0.lock() {
+            if let Some(reserved) = nonces.get_mut(address) {
+                if let Some(entry) = reserved.get_mut(nonce) {
+                    *entry = Some(hash);
+                }
+            }
+        }
+    }
+
+    fn release(&self, address: &H160, nonce: &U256) {
+        if let Ok(mut nonces) = self.0.lock() {
+            if let Some(reserved) = nonces.get_mut(address) {
+                reserved.remove(nonce);
+                if reserved.is_empty() {
+                    nonces.remove(address);
+                }
+            }
+        }
+    }
+}
+
+/// A nonce reserved by `LocalNonces::reserve`. Unless committed once the pool accepted its
+/// transaction, dropping it releases the nonce, so that a transaction that failed to sign or was
+/// never submitted leaves no gap. Other reservations of the account are kept.
+pub(crate) struct NonceReservation<H: Clone> {
+    nonces: LocalNonces<H>,
+    address: H160,
+    nonce: U256,
+    committed: bool,
+}
+
+impl<H: Clone> NonceReservation<H> {
+    fn commit(mut self, hash: H) {
+        self.nonces.commit(&self.address, &self.nonce, hash);
+        self.committed = true;
+    }
+}
+
+impl<H: Clone> Drop for NonceReservation<H> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.nonces.release(&self.address, &self.nonce);
+        }
+    }
+}
+
+/// One of the concurrent executions allowed by `EthApi::new`, given back when dropped.
+struct ExecutionPermit(Arc<AtomicUsize>);
+
+impl ExecutionPermit {
+    fn acquire(running: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        let mut current = running.load(Ordering::Acquire);
+        loop {
+            if current >= max {
+                return None;
+            }
+            match running.compare_exchange_weak(
+                current,
+                current + 1,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(Self(running.clone())),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+impl Drop for ExecutionPermit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
 fn rich_block_build(
     block: ethereum::Block,
     statuses: Vec<Option<TransactionStatus>>,
@@ -141,7 +319,92 @@ fn rich_block_build(
     }
 }
 
-fn transaction_build(
+/// Resolve `id` to the Ethereum block hash the client-side Ethereum block store is keyed by.
+pub(crate) fn ethereum_block_hash<B: BlockT, C: HeaderBackend<B> + AuxStore>(
+    client: &C,
+    id: &BlockId<B>,
+) -> Option<H256> {
+    let hash = match id {
+        BlockId::Hash(hash) => *hash,
+        BlockId::Number(number) => client.hash(*number).unwrap_or(None)?,
+    };
+    stored(ac_consensus::ethereum_block_hash::<B, _>(client, hash))
+}
+
+/// `EthereumRuntimeRPCApi` version of the runtime at `id`.
+pub(crate) fn runtime_api_version<B, C>(client: &C, id: &BlockId<B>) -> Result<u32>
+    where
+        B: BlockT,
+        C: ProvideRuntimeApi<B>,
+        C::Api: EthereumRuntimeRPCApi<B>,
+{
+    client
+        .runtime_api()
+        .api_version::<dyn EthereumRuntimeRPCApi<B>>(id)
+        .map_err(|err| internal_err(format!("fetch runtime api version failed: {:?}", err)))?
+        .ok_or_else(|| internal_err("runtime does not provide the Ethereum RPC API"))
+}
+
+/// Fail unless the runtime at `id` has at least version `required` of `EthereumRuntimeRPCApi`,
+/// which `feature` needs.
+pub(crate) fn require_api_version<B, C>(
+    client: &C,
+    id: &BlockId<B>,
+    required: u32,
+    feature: &str,
+) -> Result<()>
+    where
+        B: BlockT,
+        C: ProvideRuntimeApi<B>,
+        C::Api: EthereumRuntimeRPCApi<B>,
+{
+    if runtime_api_version(client, id)? < required {
+        return Err(internal_err(format!("runtime does not support {} at this block", feature)));
+    }
+    Ok(())
+}
+
+/// Entry of the client-side Ethereum block store. Read errors, such as an entry that no longer
+/// decodes, are logged and read as a missing entry so that callers fall back to runtime state;
+/// if that state is pruned as well, the block reads as unknown.
+pub(crate) fn stored<T>(res: sp_blockchain::Result<Option<T>>) -> Option<T> {
+    res.unwrap_or_else(|err| {
+        warn!(target: "rpc", "Ethereum block store read failed: {:?}", err);
+        None
+    })
+}
+
+/// Map transaction pool rejections to the messages geth returns for the same conditions.
+/// Whether `new_gas_price` may replace a pooled transaction paying `old_gas_price`.
+fn meets_price_bump(old_gas_price: U256, new_gas_price: U256) -> bool {
+    let percent = U256::from(PRICE_BUMP_PERCENT);
+    let hundred = U256::from(100);
+    // `old_gas_price * PRICE_BUMP_PERCENT / 100` without overflowing.
+    let bump = old_gas_price / hundred * percent + old_gas_price % hundred * percent / hundred;
+    new_gas_price > old_gas_price && new_gas_price >= old_gas_price.saturating_add(bump)
+}
+
+fn pool_err<E: IntoPoolError>(err: E) -> jsonrpc_core::Error {
+    match err.into_pool_error() {
+        Ok(PoolError::InvalidTransaction(InvalidTransaction::Custom(code))) => {
+            match TransactionValidationError::from_code(code) {
+                Some(e) => internal_err(e.message()),
+                None => internal_err(format!("invalid transaction: custom error {}", code)),
+            }
+        }
+        Ok(PoolError::InvalidTransaction(InvalidTransaction::Stale)) => {
+            internal_err("nonce too low")
+        }
+        Ok(PoolError::AlreadyImported(_)) => internal_err("already known"),
+        Ok(PoolError::TooLowPriority { .. }) => {
+            internal_err("replacement transaction underpriced")
+        }
+        Ok(err) => internal_err(format!("submit transaction to pool failed: {:?}", err)),
+        Err(err) => internal_err(format!("submit transaction to pool failed: {:?}", err)),
+    }
+}
+
+pub(crate) fn transaction_build(
     transaction: EthereumTransaction,
     block: Option<EthereumBlock>,
     status: Option<TransactionStatus>,
@@ -257,14 +520,130 @@ fn logs_build(
     ret
 }
 
-fn storage_prefix_build(module: &[u8], storage: &[u8]) -> Vec<u8> {
-    [twox_128(module), twox_128(storage)].concat().to_vec()
+fn state_overrides_build(
+    overrides: BTreeMap<H160, CallStateOverride>,
+) -> Result<Vec<(H160, StateOverride)>> {
+    overrides
+        .into_iter()
+        .map(|(address, state_override)| {
+            if state_override.state.is_some() && state_override.state_diff.is_some() {
+                return Err(internal_err(format!(
+                    "account {:?} has both 'state' and 'stateDiff'",
+                    address
+                )));
+            }
+            Ok((
+                address,
+                StateOverride {
+                    balance: state_override.balance,
+                    nonce: state_override.nonce,
+                    code: state_override.code.map(|code| code.into_vec()),
+                    state: state_override.state.map(|state| state.into_iter().collect()),
+                    state_diff: state_override
+                        .state_diff
+                        .map(|state_diff| state_diff.into_iter().collect()),
+                },
+            ))
+        })
+        .collect()
+}
+
+fn block_override_build(overrides: BlockOverrides) -> BlockOverride {
+    BlockOverride {
+        number: overrides.number,
+        time: overrides.time,
+        coinbase: overrides.coinbase,
+        gas_limit: overrides.gas_limit,
+    }
+}
+
+/// Reject an `eth_simulateV1` request with too many blocks or calls, or whose calls together may
+/// use more than `gas_cap`, before anything is executed.
+fn check_simulate_limits(blocks: &[SimulateBlock], gas_cap: U256) -> Result<()> {
+    if blocks.len() > MAX_SIMULATE_BLOCKS {
+        return Err(internal_err(format!(
+            "too many blocks: {} > {}",
+            blocks.len(),
+            MAX_SIMULATE_BLOCKS
+        )));
+    }
+    let mut total_gas = U256::zero();
+    for block in blocks {
+        if block.calls.len() > MAX_SIMULATE_CALLS_PER_BLOCK {
+            return Err(internal_err(format!(
+                "too many calls in block: {} > {}",
+                block.calls.len(),
+                MAX_SIMULATE_CALLS_PER_BLOCK
+            )));
+        }
+        for call in &block.calls {
+            total_gas = total_gas.saturating_add(call.gas_limit);
+        }
+    }
+    if total_gas > gas_cap {
+        return Err(internal_err(format!(
+            "total gas of all calls exceeds the gas cap: {} > {}",
+            total_gas, gas_cap
+        )));
+    }
+    Ok(())
 }
 
-fn blake2_128_extend(bytes: &[u8]) -> Vec<u8> {
-    let mut ext: Vec<u8> = blake2_128(bytes).to_vec();
-    ext.extend_from_slice(bytes);
-    ext
+fn simulated_call_build(
+    result: std::result::Result<CallOrCreateInfo, DispatchError>,
+    block_log_index: &mut u32,
+) -> SimulatedCall {
+    let (exit_reason, return_data, used_gas, logs) = match result {
+        Ok(CallOrCreateInfo::Call(info)) => {
+            (info.exit_reason, info.value, info.used_gas, info.logs)
+        }
+        Ok(CallOrCreateInfo::Create(info)) => (
+            info.exit_reason,
+            info.value[..].to_vec(),
+            info.used_gas,
+            info.logs,
+        ),
+        Err(err) => {
+            return SimulatedCall {
+                return_data: Bytes(Vec::new()),
+                logs: Vec::new(),
+                gas_used: U256::zero(),
+                status: U64::zero(),
+                error: Some(internal_err(format!("execution fatal: {:?}", err))),
+            }
+        }
+    };
+    let error = error_on_execution_failure(&exit_reason, &return_data).err();
+    SimulatedCall {
+        logs: logs
+            .into_iter()
+            .enumerate()
+            .map(|(i, log)| {
+                let log = Log {
+                    address: log.address,
+                    topics: log.topics,
+                    data: Bytes(log.data),
+                    block_hash: None,
+                    block_number: None,
+                    transaction_hash: None,
+                    transaction_index: None,
+                    log_index: Some(U256::from(*block_log_index)),
+                    transaction_log_index: Some(U256::from(i)),
+                    removed: false,
+                };
+                *block_log_index += 1;
+                log
+            })
+            .collect(),
+        return_data: Bytes(return_data),
+        gas_used: used_gas,
+        status: if error.is_none() {
+            U64::one()
+        } else {
+            U64::zero()
+        },
+        error,
+    }
 }
 
 impl<B, C, P, CT, BE, H: ExHashT, A> EthApi<B, C, P, CT, BE, H, A>
@@ -280,6 +659,192 @@ impl<B, C, P, CT, BE, H: ExHashT, A> EthApi<B, C, P, CT, BE, H, A>
         A: ChainApi<Block = B> + 'static,
         CT: ConvertTransaction<<B as BlockT>::Extrinsic> + Send + Sync + 'static,
 {
+    pub(crate) fn signers(&self) -> &[Box<dyn EthSigner>] {
+        &self.signers
+    }
+
+    /// Whether the runtime at `id` has the Ethereum API. Unavailable state, e.g. pruned, is an
+    /// error rather than reading as accounts with nothing in them.
+    fn has_ethereum_api(&self, id: &BlockId<B>) -> Result<bool> {
+        ac_consensus::has_ethereum_api(self.client.as_ref(), id)
+            .map_err(|err| internal_err(format!("state unavailable: {:?}", err)))
+    }
+
+    /// The message to sign for `request`, with the reservation of its nonce when the request
+    /// does not set one.
+    pub(crate) fn transaction_message(
+        &self,
+        request: TransactionRequest,
+    ) -> Result<(H160, ethereum::TransactionMessage, Option<NonceReservation<ExtrinsicHash<A>>>)> {
+        let from = match request.from {
+            Some(from) => from,
+            None => match self.accounts()?.get(0) {
+                Some(account) => account.clone(),
+                None => return Err(internal_err("no signer available")),
+            },
+        };
+        let gas_price = match request.gas_price {
+            Some(gas_price) => gas_price,
+            None => self.gas_price()?,
+        };
+        let gas_limit = match request.gas {
+            Some(gas_limit) => gas_limit,
+            None => self.estimate_gas(
+                CallRequest {
+                    from: Some(from),
+                    to: request.to,
+                    gas_price: Some(gas_price),
+                    gas: None,
+                    value: request.value,
+                    data: request.data.clone(),
+                    nonce: None,
+                    salt: None,
+                },
+                None,
+            )?,
+        };
+        let (nonce, reservation) = match request.nonce {
+            Some(nonce) => (nonce, None),
+            None => {
+                let validated_pool = self.graph.validated_pool();
+                let reservation = self.local_nonces.reserve(
+                    from,
+                    || self.transaction_count(from, Some(BlockNumber::Pending)),
+                    |hash| validated_pool.extrinsics_tags(&[hash.clone()])[0].is_some(),
+                )?;
+                (reservation.nonce, Some(reservation))
+            }
+        };
+        let chain_id = self.chain_id()?;
+        let message = ethereum::TransactionMessage {
+            nonce,
+            gas_price,
+            gas_limit,
+            value: request.value.unwrap_or(U256::zero()),
+            input: request.data.map(|s| s.into_vec()).unwrap_or_default(),
+            action: match request.to {
+                Some(to) => ethereum::TransactionAction::Call(to),
+                None => ethereum::TransactionAction::Create,
+            },
+            chain_id: chain_id.map(|s| s.as_u64()),
+        };
+        Ok((from, message, reservation))
+    }
+
+    /// Submit a transaction signed by a local account, keeping its nonce reservation only if the
+    /// pool accepts it.
+    pub(crate) fn submit_local_transaction(
+        &self,
+        reservation: Option<NonceReservation<ExtrinsicHash<A>>>,
+        transaction: ethereum::Transaction,
+    ) -> BoxFuture<H256> {
+        let uxt = self
+            .convert_transaction
+            .convert_transaction(transaction.clone());
+        let (uxt_hash, _bytes) = self.graph.validated_pool().api().hash_and_length(&uxt);
+        Box::new(self.submit_transaction(transaction).then(move |result| {
+            if let (Ok(_), Some(reservation)) = (&result, reservation) {
+                reservation.commit(uxt_hash);
+            }
+            result
+        }))
+    }
+
+    /// Fail if `transaction` would replace a pooled transaction with the same sender and nonce
+    /// without raising the gas price by `PRICE_BUMP_PERCENT`. The pool itself accepts any higher
+    /// gas price.
+    fn check_price_bump(&self, transaction: &ethereum::Transaction) -> Result<()> {
+        let from = match public_key(transaction) {
+            Ok(pk) => H160::from(H256::from_slice(Keccak256::digest(&pk).as_slice())),
+            // Rejected by the pool.
+            Err(_) => return Ok(()),
+        };
+        let tag = (from, transaction.nonce).encode();
+        let validated_pool = self.graph.validated_pool();
+        let mut pooled = validated_pool
+            .ready()
+            .filter(|tx| tx.provides().contains(&tag))
+            .map(|tx| tx.data().clone())
+            .collect::<Vec<_>>();
+        let futures = validated_pool.futures();
+        let hashes = futures.iter().map(|(hash, _)| hash.clone()).collect::<Vec<_>>();
+        for ((_, xt), tags) in futures.into_iter().zip(validated_pool.extrinsics_tags(&hashes)) {
+            if tags.map_or(false, |tags| tags.contains(&tag)) {
+                pooled.push(xt);
+            }
+        }
+        if pooled.is_empty() {
+            return Ok(());
+        }
+        let id = BlockId::Hash(self.client.info().best_hash);
+        // Without `extrinsic_filter` the pooled gas price cannot be read.
+        if runtime_api_version(self.client.as_ref(), &id)? < 2 {
+            return Ok(());
+        }
+        let pooled = self
+            .client
+            .runtime_api()
+            .extrinsic_filter(&id, pooled)
+            .map_err(|err| internal_err(format!("fetch pool transactions failed: {:?}", err)))?;
+        for pooled in pooled {
+            if !meets_price_bump(pooled.gas_price, transaction.gas_price) {
+                return Err(internal_err("replacement transaction underpriced"));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn submit_transaction(
+        &self,
+        transaction: ethereum::Transaction,
+    ) -> BoxFuture<H256> {
+        let transaction_hash =
+            H256::from_slice(Keccak256::digest(&rlp::encode(&transaction)).as_slice());
+        let hash = self.client.info().best_hash;
+        let number = self.client.info().best_number;
+        let uxt = self
+            .convert_transaction
+            .convert_transaction(transaction.clone());
+        let (uxt_hash, _bytes) = self.graph.validated_pool().api().hash_and_length(&uxt);
+        let check_is_known = self.graph.validated_pool().check_is_known(&uxt_hash, false);
+        match check_is_known {
+            Ok(_) => {
+                if let Err(e) = self.check_price_bump(&transaction) {
+                    return Box::new(future::result(Err(e)));
+                }
+                let pending = self.pending_transactions.clone();
+                Box::new(
+                    self.pool
+                        .submit_one(
+                            &BlockId::hash(hash),
+                            TransactionSource::Local,
+                            self.convert_transaction
+                                .convert_transaction(transaction.clone()),
+                        )
+                        .compat()
+                        .map(move |_| {
+                            if let Some(pending) = pending {
+                                if let Ok(locked) = &mut pending.lock() {
+                                    locked.insert(
+                                        transaction_hash,
+                                        PendingTransaction::new(
+                                            transaction_build(transaction, None, None),
+                                            UniqueSaturatedInto::<u64>::unique_saturated_into(
+                                                number,
+                                            ),
+                                        ),
+                                    );
+                                }
+                            }
+                            transaction_hash
+                        })
+                        .map_err(pool_err),
+                )
+            }
+            _ => Box::new(futures::future::ok(transaction_hash).compat()),
+        }
+    }
+
     fn native_block_id(&self, number: Option<BlockNumber>) -> Result<Option<BlockId<B>>> {
         Ok(match number.unwrap_or(BlockNumber::Latest) {
             BlockNumber::Hash { hash, .. } => self.load_hash(hash).unwrap_or(None),
@@ -365,51 +930,106 @@ impl<B, C, P, CT, BE, H: ExHashT, A> EthApi<B, C, P, CT, BE, H, A>
     }
 
     fn current_block(&self, id: &BlockId<B>) -> Option<ethereum::Block> {
-        self.query_storage::<ethereum::Block>(
-            id,
-            &StorageKey(storage_prefix_build(b"Ethereum", b"CurrentBlock")),
-        )
+        ethereum_block_hash(self.client.as_ref(), id)
+            .and_then(|hash| {
+                stored(ac_consensus::load_ethereum_block(self.client.as_ref(), hash))
+            })
+            .or_else(|| {
+                self.overrides
+                    .for_block(self.client.as_ref(), id)
+                    .current_block(id)
+            })
     }
 
     fn current_statuses(&self, id: &BlockId<B>) -> Option<Vec<TransactionStatus>> {
-        self.query_storage::<Vec<TransactionStatus>>(
-            id,
-            &StorageKey(storage_prefix_build(
-                b"Ethereum",
-                b"CurrentTransactionStatuses",
-            )),
-        )
+        ethereum_block_hash(self.client.as_ref(), id)
+            .and_then(|hash| {
+                stored(ac_consensus::load_ethereum_statuses(self.client.as_ref(), hash))
+            })
+            .or_else(|| {
+                self.overrides
+                    .for_block(self.client.as_ref(), id)
+                    .current_transaction_statuses(id)
+            })
     }
 
     fn current_receipts(&self, id: &BlockId<B>) -> Option<Vec<ethereum::Receipt>> {
-        self.query_storage::<Vec<ethereum::Receipt>>(
-            id,
-            &StorageKey(storage_prefix_build(b"Ethereum", b"CurrentReceipts")),
-        )
+        ethereum_block_hash(self.client.as_ref(), id)
+            .and_then(|hash| {
+                stored(ac_consensus::load_ethereum_receipts(self.client.as_ref(), hash))
+            })
+            .or_else(|| {
+                self.overrides
+                    .for_block(self.client.as_ref(), id)
+                    .current_receipts(id)
+            })
     }
 
     fn account_codes(&self, id: &BlockId<B>, address: H160) -> Option<Vec<u8>> {
-        let mut key: Vec<u8> = storage_prefix_build(b"EVM", b"AccountCodes");
-        key.extend(blake2_128_extend(address.as_bytes()));
-        self.query_storage::<Vec<u8>>(id, &StorageKey(key))
+        self.overrides
+            .for_block(self.client.as_ref(), id)
+            .account_code_at(id, address)
     }
 
     fn account_storages(&self, id: &BlockId<B>, address: H160, index: U256) -> Option<H256> {
-        let tmp: &mut [u8; 32] = &mut [0; 32];
-        index.to_little_endian(tmp);
-        let mut key: Vec<u8> = storage_prefix_build(b"EVM", b"AccountStorages");
-        key.extend(blake2_128_extend(address.as_bytes()));
-        key.extend(blake2_128_extend(tmp));
-        self.query_storage::<H256>(id, &StorageKey(key))
-    }
-
-    fn query_storage<T: Decode>(&self, id: &BlockId<B>, key: &StorageKey) -> Option<T> {
-        if let Ok(Some(data)) = self.client.storage(id, key) {
-            if let Ok(result) = Decode::decode(&mut &data.0[..]) {
-                return Some(result);
+        self.overrides
+            .for_block(self.client.as_ref(), id)
+            .storage_at(id, address, index)
+    }
+
+    /// Gas limit to execute a request with: `default` when the caller did not ask for one,
+    /// bounded by the RPC gas cap.
+    fn capped_gas_limit(&self, requested: Option<U256>, default: U256) -> Result<U256> {
+        match requested {
+            Some(requested) if requested > self.gas_cap => Err(internal_err(format!(
+                "gas limit {} exceeds the RPC gas cap {}",
+                requested, self.gas_cap
+            ))),
+            Some(requested) => Ok(requested),
+            None => Ok(std::cmp::min(default, self.gas_cap)),
+        }
+    }
+
+    /// `EthereumRuntimeRPCApi` version of the runtime at `id`, checked to support `salt`.
+    fn api_version(&self, id: &BlockId<B>, salt: Option<H256>) -> Result<u32> {
+        let version = runtime_api_version(self.client.as_ref(), id)?;
+        if salt.is_some() && version < 2 {
+            return Err(internal_err("runtime does not support salt (CREATE2) at this block"));
+        }
+        Ok(version)
+    }
+
+    /// Runtime API executions cannot be interrupted, so on timeout the request fails while the
+    /// execution keeps running in the background until it runs out of gas.
+    fn execute_with_timeout<R, F>(&self, f: F) -> Result<R>
+        where
+            R: Send + 'static,
+            F: FnOnce(&C) -> Result<R> + Send + 'static,
+    {
+        // Without a timeout every execution runs on the request thread, which bounds them.
+        let timeout = match self.execute_timeout {
+            Some(timeout) => timeout,
+            None => return f(&self.client),
+        };
+        let permit =
+            ExecutionPermit::acquire(&self.running_executions, self.max_concurrent_executions)
+                .ok_or_else(|| internal_err("too many concurrent executions, try again later"))?;
+        let client = self.client.clone();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(f(&client));
+            drop(permit);
+        });
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(internal_err(format!(
+                "execution aborted (timeout = {:?})",
+                timeout
+            ))),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(internal_err("execution thread terminated unexpectedly"))
             }
         }
-        None
     }
 }
 
@@ -504,6 +1124,10 @@ impl<B, C, P, CT, BE, H: ExHashT, A> EthApiT for EthApi<B, C, P, CT, BE, H, A>
 
     fn balance(&self, address: H160, number: Option<BlockNumber>) -> Result<U256> {
         if let Ok(Some(id)) = self.native_block_id(number) {
+            // Accounts have no EVM balance before `pallet_ethereum` was added.
+            if !self.has_ethereum_api(&id)? {
+                return Ok(U256::zero());
+            }
             return Ok(self
                 .client
                 .runtime_api()
@@ -598,6 +1222,9 @@ impl<B, C, P, CT, BE, H: ExHashT, A> EthApiT for EthApi<B, C, P, CT, BE, H, A>
             Some(id) => id,
             None => return Ok(U256::zero()),
         };
+        if !self.has_ethereum_api(&id)? {
+            return Ok(U256::zero());
+        }
         let nonce = self
             .client
             .runtime_api()
@@ -660,44 +1287,10 @@ impl<B, C, P, CT, BE, H: ExHashT, A> EthApiT for EthApi<B, C, P, CT, BE, H, A>
     }
 
     fn send_transaction(&self, request: TransactionRequest) -> BoxFuture<H256> {
-        let from = match request.from {
-            Some(from) => from,
-            None => {
-                let accounts = match self.accounts() {
-                    Ok(accounts) => accounts,
-                    Err(e) => return Box::new(future::result(Err(e))),
-                };
-                match accounts.get(0) {
-                    Some(account) => account.clone(),
-                    None => {
-                        return Box::new(future::result(Err(internal_err("no signer available"))))
-                    }
-                }
-            }
-        };
-        let nonce = match request.nonce {
-            Some(nonce) => nonce,
-            None => match self.transaction_count(from, None) {
-                Ok(nonce) => nonce,
-                Err(e) => return Box::new(future::result(Err(e))),
-            },
-        };
-        let chain_id = match self.chain_id() {
-            Ok(chain_id) => chain_id,
+        let (from, message, reservation) = match self.transaction_message(request) {
+            Ok(res) => res,
             Err(e) => return Box::new(future::result(Err(e))),
         };
-        let message = ethereum::TransactionMessage {
-            nonce,
-            gas_price: request.gas_price.unwrap_or(U256::from(1)),
-            gas_limit: request.gas.unwrap_or(U256::max_value()),
-            value: request.value.unwrap_or(U256::zero()),
-            input: request.data.map(|s| s.into_vec()).unwrap_or_default(),
-            action: match request.to {
-                Some(to) => ethereum::TransactionAction::Call(to),
-                None => ethereum::TransactionAction::Create,
-            },
-            chain_id: chain_id.map(|s| s.as_u64()),
-        };
         let mut transaction = None;
         for signer in &self.signers {
             if signer.accounts().contains(&from) {
@@ -708,53 +1301,9 @@ impl<B, C, P, CT, BE, H: ExHashT, A> EthApiT for EthApi<B, C, P, CT, BE, H, A>
                 break;
             }
         }
-        let transaction = match transaction {
-            Some(transaction) => transaction,
-            None => return Box::new(future::result(Err(internal_err("no signer available")))),
-        };
-        let transaction_hash =
-            H256::from_slice(Keccak256::digest(&rlp::encode(&transaction)).as_slice());
-        let hash = self.client.info().best_hash;
-        let number = self.client.info().best_number;
-        let uxt = self
-            .convert_transaction
-            .convert_transaction(transaction.clone());
-        let (uxt_hash, _bytes) = self.graph.validated_pool().api().hash_and_length(&uxt);
-        let check_is_known = self.graph.validated_pool().check_is_known(&uxt_hash, false);
-        match check_is_known {
-            Ok(_) => {
-                let pending = self.pending_transactions.clone();
-                Box::new(
-                    self.pool
-                        .submit_one(
-                            &BlockId::hash(hash),
-                            TransactionSource::Local,
-                            self.convert_transaction
-                                .convert_transaction(transaction.clone()),
-                        )
-                        .compat()
-                        .map(move |_| {
-                            if let Some(pending) = pending {
-                                if let Ok(locked) = &mut pending.lock() {
-                                    locked.insert(
-                                        transaction_hash,
-                                        PendingTransaction::new(
-                                            transaction_build(transaction, None, None),
-                                            UniqueSaturatedInto::<u64>::unique_saturated_into(
-                                                number,
-                                            ),
-                                        ),
-                                    );
-                                }
-                            }
-                            transaction_hash
-                        })
-                        .map_err(|err| {
-                            internal_err(format!("submit transaction to pool failed: {:?}", err))
-                        }),
-                )
-            }
-            _ => Box::new(futures::future::ok(transaction_hash).compat()),
+        match transaction {
+            Some(transaction) => self.submit_local_transaction(reservation, transaction),
+            None => Box::new(future::result(Err(internal_err("no signer available")))),
         }
     }
 
@@ -767,55 +1316,51 @@ impl<B, C, P, CT, BE, H: ExHashT, A> EthApiT for EthApi<B, C, P, CT, BE, H, A>
                 ))))
             }
         };
-        let transaction_hash =
-            H256::from_slice(Keccak256::digest(&rlp::encode(&transaction)).as_slice());
-        let hash = self.client.info().best_hash;
-        let number = self.client.info().best_number;
+        self.submit_transaction(transaction)
+    }
 
-        let uxt = self
-            .convert_transaction
-            .convert_transaction(transaction.clone());
-        let (uxt_hash, _bytes) = self.graph.validated_pool().api().hash_and_length(&uxt);
-        let check_is_known = self.graph.validated_pool().check_is_known(&uxt_hash, false);
+    fn sign(&self, address: H160, data: Bytes) -> Result<Bytes> {
+        for signer in &self.signers {
+            if signer.accounts().contains(&address) {
+                return signer
+                    .sign_message(&address, &data.0)
+                    .map(|signature| Bytes(signature.to_vec()));
+            }
+        }
+        Err(internal_err("no signer available"))
+    }
 
-        match check_is_known {
-            Ok(_) => {
-                let pending = self.pending_transactions.clone();
-                Box::new(
-                    self.pool
-                        .submit_one(
-                            &BlockId::hash(hash),
-                            TransactionSource::Local,
-                            self.convert_transaction
-                                .convert_transaction(transaction.clone()),
-                        )
-                        .compat()
-                        .map(move |_| {
-                            if let Some(pending) = pending {
-                                if let Ok(locked) = &mut pending.lock() {
-                                    locked.insert(
-                                        transaction_hash,
-                                        PendingTransaction::new(
-                                            transaction_build(transaction, None, None),
-                                            UniqueSaturatedInto::<u64>::unique_saturated_into(
-                                                number,
-                                            ),
-                                        ),
-                                    );
-                                }
-                            }
-                            transaction_hash
-                        })
-                        .map_err(|err| {
-                            internal_err(format!("submit transaction to pool failed: {:?}", err))
-                        }),
-                )
+    fn sign_typed_data(&self, address: H160, typed_data: TypedData) -> Result<Bytes> {
+        for signer in &self.signers {
+            if signer.accounts().contains(&address) {
+                return signer
+                    .sign_typed_data(&address, &typed_data)
+                    .map(|signature| Bytes(signature.to_vec()));
             }
-            _ => Box::new(futures::future::ok(transaction_hash).compat()),
         }
+        Err(internal_err("no signer available"))
     }
 
-    fn call(&self, request: CallRequest, _: Option<BlockNumber>) -> Result<Bytes> {
+    fn sign_transaction(&self, request: TransactionRequest) -> Result<Bytes> {
+        // Never submitted, so the reservation is released when this returns.
+        let (from, message, _reservation) = self.transaction_message(request)?;
+        for signer in &self.signers {
+            if signer.accounts().contains(&from) {
+                return signer
+                    .sign(message, &from)
+                    .map(|transaction| Bytes(rlp::encode(&transaction).to_vec()));
+            }
+        }
+        Err(internal_err("no signer available"))
+    }
+
+    fn call(
+        &self,
+        request: CallRequest,
+        _: Option<BlockNumber>,
+        state_overrides: Option<BTreeMap<H160, CallStateOverride>>,
+        block_overrides: Option<BlockOverrides>,
+    ) -> Result<Bytes> {
         let hash = self.client.info().best_hash;
         let CallRequest {
             from,
@@ -825,15 +1370,29 @@ impl<B, C, P, CT, BE, H: ExHashT, A> EthApiT for EthApi<B, C, P, CT, BE, H, A>
             value,
             data,
             nonce,
+            salt,
         } = request;
-        let gas_limit = gas.unwrap_or(U256::max_value()); // TODO: set a limit
+        if salt.is_some() && to.is_some() {
+            return Err(internal_err("salt is only valid for contract creation"));
+        }
+        let gas_limit = self.capped_gas_limit(gas, U256::max_value())?;
         let data = data.map(|d| d.0).unwrap_or_default();
-        match to {
-            Some(to) => {
-                let info = self
-                    .client
+        if state_overrides.is_some() || block_overrides.is_some() {
+            if salt.is_some() {
+                return Err(internal_err("salt is not supported with overrides"));
+            }
+            require_api_version(
+                self.client.as_ref(),
+                &BlockId::Hash(hash),
+                2,
+                "state and block overrides",
+            )?;
+            let state_overrides = state_overrides_build(state_overrides.unwrap_or_default())?;
+            let block_override = block_overrides.map(block_override_build);
+            let info = self.execute_with_timeout(move |client| {
+                client
                     .runtime_api()
-                    .call(
+                    .call_with_overrides(
                         &BlockId::Hash(hash),
                         from.unwrap_or_default(),
                         to,
@@ -843,34 +1402,121 @@ impl<B, C, P, CT, BE, H: ExHashT, A> EthApiT for EthApi<B, C, P, CT, BE, H, A>
                         gas_price,
                         nonce,
                         false,
+                        state_overrides,
+                        block_override,
                     )
                     .map_err(|err| internal_err(format!("runtime error: {:?}", err)))?
-                    .map_err(|err| internal_err(format!("execution fatal: {:?}", err)))?;
+                    .map_err(|err| internal_err(format!("execution fatal: {:?}", err)))
+            })?;
+            return match info {
+                CallOrCreateInfo::Call(info) => {
+                    error_on_execution_failure(&info.exit_reason, &info.value)?;
+                    Ok(Bytes(info.value))
+                }
+                CallOrCreateInfo::Create(info) => {
+                    error_on_execution_failure(&info.exit_reason, &[])?;
+                    Ok(Bytes(info.value[..].to_vec()))
+                }
+            };
+        }
+        match to {
+            Some(to) => {
+                let info = self.execute_with_timeout(move |client| {
+                    client
+                        .runtime_api()
+                        .call(
+                            &BlockId::Hash(hash),
+                            from.unwrap_or_default(),
+                            to,
+                            data,
+                            value.unwrap_or_default(),
+                            gas_limit,
+                            gas_price,
+                            nonce,
+                            false,
+                        )
+                        .map_err(|err| internal_err(format!("runtime error: {:?}", err)))?
+                        .map_err(|err| internal_err(format!("execution fatal: {:?}", err)))
+                })?;
                 error_on_execution_failure(&info.exit_reason, &info.value)?;
                 Ok(Bytes(info.value))
             }
             None => {
-                let info = self
-                    .client
-                    .runtime_api()
-                    .create(
-                        &BlockId::Hash(hash),
-                        from.unwrap_or_default(),
-                        data,
-                        value.unwrap_or_default(),
-                        gas_limit,
-                        gas_price,
-                        nonce,
-                        false,
-                    )
-                    .map_err(|err| internal_err(format!("runtime error: {:?}", err)))?
-                    .map_err(|err| internal_err(format!("execution fatal: {:?}", err)))?;
+                let version = self.api_version(&BlockId::Hash(hash), salt)?;
+                let info = self.execute_with_timeout(move |client| {
+                    let api = client.runtime_api();
+                    let id = BlockId::Hash(hash);
+                    let from = from.unwrap_or_default();
+                    let value = value.unwrap_or_default();
+                    #[allow(deprecated)]
+                    let info = if version >= 2 {
+                        api.create(&id, from, data, value, gas_limit, gas_price, nonce, salt, false)
+                    } else {
+                        api.create_before_version_2(
+                            &id, from, data, value, gas_limit, gas_price, nonce, false,
+                        )
+                    };
+                    info.map_err(|err| internal_err(format!("runtime error: {:?}", err)))?
+                        .map_err(|err| internal_err(format!("execution fatal: {:?}", err)))
+                })?;
                 error_on_execution_failure(&info.exit_reason, &[])?;
                 Ok(Bytes(info.value[..].to_vec()))
             }
         }
     }
 
+    fn simulate(
+        &self,
+        payload: SimulatePayload,
+        number: Option<BlockNumber>,
+    ) -> Result<Vec<SimulatedBlock>> {
+        let id = match self.native_block_id(number)? {
+            Some(id) => id,
+            None => BlockId::Hash(self.client.info().best_hash),
+        };
+        let mut blocks = Vec::new();
+        for block in payload.block_state_calls {
+            let mut calls = Vec::new();
+            for request in block.calls {
+                calls.push(SimulateCall {
+                    from: request.from.unwrap_or_default(),
+                    to: request.to,
+                    data: request.data.map(|d| d.0).unwrap_or_default(),
+                    value: request.value.unwrap_or_default(),
+                    gas_limit: self.capped_gas_limit(request.gas, U256::max_value())?,
+                    gas_price: request.gas_price,
+                    nonce: request.nonce,
+                });
+            }
+            blocks.push(SimulateBlock {
+                state_overrides: state_overrides_build(block.state_overrides.unwrap_or_default())?,
+                block_override: block.block_overrides.map(block_override_build),
+                calls,
+            });
+        }
+        check_simulate_limits(&blocks, self.gas_cap)?;
+        require_api_version(self.client.as_ref(), &id, 2, "eth_simulateV1")?;
+        let trace_transfers = payload.trace_transfers;
+        let results = self.execute_with_timeout(move |client| {
+            client
+                .runtime_api()
+                .simulate(&id, blocks, trace_transfers)
+                .map_err(|err| internal_err(format!("runtime error: {:?}", err)))
+        })?;
+        Ok(results
+            .into_iter()
+            .map(|calls| {
+                let mut block_log_index: u32 = 0;
+                SimulatedBlock {
+                    calls: calls
+                        .into_iter()
+                        .map(|result| simulated_call_build(result, &mut block_log_index))
+                        .collect(),
+                }
+            })
+            .collect())
+    }
+
     fn estimate_gas(&self, request: CallRequest, _: Option<BlockNumber>) -> Result<U256> {
         let calculate_gas_used = |request| {
             let hash = self.client.info().best_hash;
@@ -882,52 +1528,61 @@ impl<B, C, P, CT, BE, H: ExHashT, A> EthApiT for EthApi<B, C, P, CT, BE, H, A>
                 value,
                 data,
                 nonce,
+                salt,
             } = request;
+            if salt.is_some() && to.is_some() {
+                return Err(internal_err("salt is only valid for contract creation"));
+            }
             let block_gas_limit: U256 = self
                 .client
                 .runtime_api()
                 .current_block_gas_limit(&BlockId::Hash(hash))
                 .map_err(|err| internal_err(format!("runtime error: {:?}", err)))?;
-            let gas_limit = gas.unwrap_or(block_gas_limit);
+            let gas_limit = self.capped_gas_limit(gas, block_gas_limit)?;
             let data = data.map(|d| d.0).unwrap_or_default();
             let used_gas = match to {
                 Some(to) => {
-                    let info = self
-                        .client
-                        .runtime_api()
-                        .call(
-                            &BlockId::Hash(hash),
-                            from.unwrap_or_default(),
-                            to,
-                            data,
-                            value.unwrap_or_default(),
-                            gas_limit,
-                            gas_price,
-                            nonce,
-                            true,
-                        )
-                        .map_err(|err| internal_err(format!("runtime error: {:?}", err)))?
-                        .map_err(|err| internal_err(format!("execution fatal: {:?}", err)))?;
+                    let info = self.execute_with_timeout(move |client| {
+                        client
+                            .runtime_api()
+                            .call(
+                                &BlockId::Hash(hash),
+                                from.unwrap_or_default(),
+                                to,
+                                data,
+                                value.unwrap_or_default(),
+                                gas_limit,
+                                gas_price,
+                                nonce,
+                                true,
+                            )
+                            .map_err(|err| internal_err(format!("runtime error: {:?}", err)))?
+                            .map_err(|err| internal_err(format!("execution fatal: {:?}", err)))
+                    })?;
 
                     error_on_execution_failure(&info.exit_reason, &info.value)?;
                     info.used_gas
                 }
                 None => {
-                    let info = self
-                        .client
-                        .runtime_api()
-                        .create(
-                            &BlockId::Hash(hash),
-                            from.unwrap_or_default(),
-                            data,
-                            value.unwrap_or_default(),
-                            gas_limit,
-                            gas_price,
-                            nonce,
-                            true,
-                        )
-                        .map_err(|err| internal_err(format!("runtime error: {:?}", err)))?
-                        .map_err(|err| internal_err(format!("execution fatal: {:?}", err)))?;
+                    let version = self.api_version(&BlockId::Hash(hash), salt)?;
+                    let info = self.execute_with_timeout(move |client| {
+                        let api = client.runtime_api();
+                        let id = BlockId::Hash(hash);
+                        let from = from.unwrap_or_default();
+                        let value = value.unwrap_or_default();
+                        #[allow(deprecated)]
+                        let info = if version >= 2 {
+                            api.create(
+                                &id, from, data, value, gas_limit, gas_price, nonce, salt, true,
+                            )
+                        } else {
+                            api.create_before_version_2(
+                                &id, from, data, value, gas_limit, gas_price, nonce, true,
+                            )
+                        };
+                        info.map_err(|err| internal_err(format!("runtime error: {:?}", err)))?
+                            .map_err(|err| internal_err(format!("execution fatal: {:?}", err)))
+                    })?;
                     error_on_execution_failure(&info.exit_reason, &[])?;
                     info.used_gas
                 }
@@ -936,7 +1591,7 @@ impl<B, C, P, CT, BE, H: ExHashT, A> EthApiT for EthApi<B, C, P, CT, BE, H, A>
         };
         if cfg!(feature = "rpc_binary_search_estimate") {
             let mut lower = U256::from(21_000);
-            let mut upper = U256::from(1_000_000_000);
+            let mut upper = self.capped_gas_limit(None, U256::from(1_000_000_000))?;
             let mut mid = upper;
             let mut best = mid;
             let mut old_best: U256;
@@ -1319,17 +1974,24 @@ impl<B, C> Web3ApiT for Web3Api<B, C>
     }
 }
 
-pub struct EthFilterApi<B, C, BE> {
+pub struct EthFilterApi<B: BlockT, C, BE> {
     client: Arc<C>,
+    overrides: Arc<OverrideHandle<B>>,
     filter_pool: FilterPool,
     max_stored_filters: usize,
     _marker: PhantomData<(B, BE)>,
 }
 
-impl<B, C, BE> EthFilterApi<B, C, BE> {
-    pub fn new(client: Arc<C>, filter_pool: FilterPool, max_stored_filters: usize) -> Self {
+impl<B: BlockT, C, BE> EthFilterApi<B, C, BE> {
+    pub fn new(
+        client: Arc<C>,
+        overrides: Arc<OverrideHandle<B>>,
+        filter_pool: FilterPool,
+        max_stored_filters: usize,
+    ) -> Self {
         Self {
             client,
+            overrides,
             filter_pool,
             max_stored_filters,
             _marker: PhantomData,
@@ -1339,7 +2001,8 @@ impl<B, C, BE> EthFilterApi<B, C, BE> {
 
 impl<B, C, BE> EthFilterApi<B, C, BE>
     where
-        C: StorageProvider<B, BE> + AuxStore,
+        C: ProvideRuntimeApi<B> + StorageProvider<B, BE> + AuxStore,
+        C::Api: EthereumRuntimeRPCApi<B>,
         BE: Backend<B> + 'static,
         BE::State: StateBackend<BlakeTwo256>,
         C: HeaderBackend<B> + HeaderMetadata<B, Error = BlockChainError> + 'static,
@@ -1378,35 +2041,34 @@ impl<B, C, BE> EthFilterApi<B, C, BE>
     }
 
     fn current_block(&self, id: &BlockId<B>) -> Option<ethereum::Block> {
-        self.query_storage::<ethereum::Block>(
-            id,
-            &StorageKey(storage_prefix_build(b"Ethereum", b"CurrentBlock")),
-        )
+        ethereum_block_hash(self.client.as_ref(), id)
+            .and_then(|hash| {
+                stored(ac_consensus::load_ethereum_block(self.client.as_ref(), hash))
+            })
+            .or_else(|| {
+                self.overrides
+                    .for_block(self.client.as_ref(), id)
+                    .current_block(id)
+            })
     }
 
     fn current_statuses(&self, id: &BlockId<B>) -> Option<Vec<TransactionStatus>> {
-        self.query_storage::<Vec<TransactionStatus>>(
-            id,
-            &StorageKey(storage_prefix_build(
-                b"Ethereum",
-                b"CurrentTransactionStatuses",
-            )),
-        )
-    }
-
-    fn query_storage<T: Decode>(&self, id: &BlockId<B>, key: &StorageKey) -> Option<T> {
-        if let Ok(Some(data)) = self.client.storage(id, key) {
-            if let Ok(result) = Decode::decode(&mut &data.0[..]) {
-                return Some(result);
-            }
-        }
-        None
+        ethereum_block_hash(self.client.as_ref(), id)
+            .and_then(|hash| {
+                stored(ac_consensus::load_ethereum_statuses(self.client.as_ref(), hash))
+            })
+            .or_else(|| {
+                self.overrides
+                    .for_block(self.client.as_ref(), id)
+                    .current_transaction_statuses(id)
+            })
     }
 }
 
 impl<B, C, BE> EthFilterApiT for EthFilterApi<B, C, BE>
     where
-        C: StorageProvider<B, BE> + AuxStore,
+        C: ProvideRuntimeApi<B> + StorageProvider<B, BE> + AuxStore,
+        C::Api: EthereumRuntimeRPCApi<B>,
         BE: Backend<B> + 'static,
         BE::State: StateBackend<BlakeTwo256>,
         C: HeaderBackend<B> + HeaderMetadata<B, Error = BlockChainError> + 'static,
@@ -1588,4 +2250,155 @@ impl<B, C, BE> EthFilterApiT for EthFilterApi<B, C, BE>
         };
         response
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reserve(
+        nonces: &LocalNonces<H256>,
+        address: H160,
+        pool_nonce: u64,
+        pool: &[H256],
+    ) -> NonceReservation<H256> {
+        nonces
+            .reserve(address, || Ok(U256::from(pool_nonce)), |hash| pool.contains(hash))
+            .unwrap()
+    }
+
+    #[test]
+    fn replacement_needs_a_price_bump() {
+        let old = U256::from(1_000);
+        assert!(meets_price_bump(old, U256::from(1_100)));
+        assert!(meets_price_bump(old, U256::from(2_000)));
+        assert!(!meets_price_bump(old, U256::from(1_099)));
+        assert!(!meets_price_bump(old, U256::from(1_001)));
+        assert!(!meets_price_bump(old, old));
+        // Rounding down the minimum price must not let an equal price through.
+        assert!(!meets_price_bump(U256::one(), U256::one()));
+        assert!(meets_price_bump(U256::one(), U256::from(2)));
+        let large = U256::max_value() / 2;
+        assert!(!meets_price_bump(large, large + 1));
+        assert!(meets_price_bump(large, large + large / 10));
+    }
+
+    #[test]
+    fn execution_permits_are_limited_and_given_back() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let first = ExecutionPermit::acquire(&running, 2).unwrap();
+        let second = ExecutionPermit::acquire(&running, 2).unwrap();
+        assert!(ExecutionPermit::acquire(&running, 2).is_none());
+        drop(first);
+        assert!(ExecutionPermit::acquire(&running, 2).is_some());
+        drop(second);
+        assert_eq!(running.load(Ordering::Acquire), 0);
+    }
+
+    #[test]
+    fn committed_nonce_reservations_are_kept() {
+        let nonces = LocalNonces::default();
+        let address = H160::repeat_byte(0x01);
+        let first = reserve(&nonces, address, 5, &[]);
+        assert_eq!(first.nonce, U256::from(5));
+        first.commit(H256::repeat_byte(0x05));
+        // The pool does not count the first transaction yet.
+        let second = reserve(&nonces, address, 5, &[H256::repeat_byte(0x05)]);
+        assert_eq!(second.nonce, U256::from(6));
+    }
+
+    #[test]
+    fn failed_signing_releases_nonce_reservation() {
+        let nonces = LocalNonces::default();
+        let address = H160::repeat_byte(0x01);
+        let first = reserve(&nonces, address, 5, &[]);
+        let second = reserve(&nonces, address, 5, &[]);
+        assert_eq!((first.nonce, second.nonce), (U256::from(5), U256::from(6)));
+        first.commit(H256::repeat_byte(0x05));
+        // Signing the second transaction fails, so it never reaches the pool.
+        drop(second);
+        let third = reserve(&nonces, address, 6, &[H256::repeat_byte(0x05)]);
+        assert_eq!(third.nonce, U256::from(6));
+    }
+
+    #[test]
+    fn dropped_reservation_keeps_overlapping_ones() {
+        let nonces = LocalNonces::default();
+        let address = H160::repeat_byte(0x01);
+        let first = reserve(&nonces, address, 5, &[]);
+        let second = reserve(&nonces, address, 5, &[]);
+        assert_eq!((first.nonce, second.nonce), (U256::from(5), U256::from(6)));
+        // The first request fails while the second one is still signing.
+        drop(first);
+        let third = reserve(&nonces, address, 5, &[]);
+        assert_eq!(third.nonce, U256::from(5));
+        let fourth = reserve(&nonces, address, 5, &[]);
+        assert_eq!(fourth.nonce, U256::from(7));
+        second.commit(H256::repeat_byte(0x06));
+    }
+
+    #[test]
+    fn reservations_follow_the_pool() {
+        let nonces = LocalNonces::default();
+        let address = H160::repeat_byte(0x01);
+        let first = reserve(&nonces, address, 5, &[]);
+        first.commit(H256::repeat_byte(0x05));
+        let second = reserve(&nonces, address, 5, &[H256::repeat_byte(0x05)]);
+        second.commit(H256::repeat_byte(0x06));
+        // Nonce 5 was included and the transaction with nonce 6 was dropped from the pool.
+        let third = reserve(&nonces, address, 6, &[]);
+        assert_eq!(third.nonce, U256::from(6));
+        assert_eq!(
+            nonces.0.lock().unwrap()[&address].keys().cloned().collect::<Vec<_>>(),
+            vec![U256::from(6)]
+        );
+    }
+
+    fn simulate_blocks(blocks: usize, calls: usize, gas_limit: u64) -> Vec<SimulateBlock> {
+        let call = SimulateCall {
+            from: H160::default(),
+            to: None,
+            data: Vec::new(),
+            value: U256::zero(),
+            gas_limit: U256::from(gas_limit),
+            gas_price: None,
+            nonce: None,
+        };
+        (0..blocks)
+            .map(|_| SimulateBlock {
+                state_overrides: Vec::new(),
+                block_override: None,
+                calls: vec![call.clone(); calls],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn simulate_limits_blocks_and_calls() {
+        let gas_cap = U256::max_value();
+        assert!(
+            check_simulate_limits(&simulate_blocks(MAX_SIMULATE_BLOCKS, 1, 1), gas_cap).is_ok()
+        );
+        assert!(
+            check_simulate_limits(&simulate_blocks(MAX_SIMULATE_BLOCKS + 1, 0, 0), gas_cap)
+                .is_err()
+        );
+        assert!(
+            check_simulate_limits(&simulate_blocks(1, MAX_SIMULATE_CALLS_PER_BLOCK, 1), gas_cap)
+                .is_ok()
+        );
+        assert!(check_simulate_limits(
+            &simulate_blocks(1, MAX_SIMULATE_CALLS_PER_BLOCK + 1, 1),
+            gas_cap
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn simulate_limits_total_gas() {
+        let gas_cap = U256::from(100_000);
+        assert!(check_simulate_limits(&simulate_blocks(2, 2, 25_000), gas_cap).is_ok());
+        assert!(check_simulate_limits(&simulate_blocks(2, 2, 25_001), gas_cap).is_err());
+        assert!(check_simulate_limits(&simulate_blocks(1, 2, u64::max_value()), gas_cap).is_err());
+    }
+}
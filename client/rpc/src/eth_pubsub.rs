@@ -11,12 +11,11 @@ use sp_api::{BlockId, ProvideRuntimeApi};
 use sp_blockchain::{Error as BlockChainError, HeaderBackend, HeaderMetadata};
 use sp_io::hashing::twox_128;
 use sp_runtime::traits::{BlakeTwo256, Block as BlockT, UniqueSaturatedInto};
-use sp_storage::{StorageData, StorageKey};
+use sp_storage::StorageKey;
 use sp_transaction_pool::TransactionPool;
 use std::collections::BTreeMap;
 use std::{iter, marker::PhantomData, sync::Arc};
 
-use codec::Decode;
 use ethereum_types::{H256, U256};
 use ac_rpc_core::types::{
     pubsub::{Kind, Params, PubSubSyncStatus, Result as PubSubResult},
@@ -33,7 +32,8 @@ use sha3::{Digest, Keccak256};
 pub use ac_rpc_core::EthPubSubApiServer;
 use futures::{StreamExt as _, TryStreamExt as _};
 
-use ap_rpc::{EthereumRuntimeRPCApi, TransactionStatus};
+use crate::{eth::stored, overrides::OverrideHandle};
+use ap_rpc::EthereumRuntimeRPCApi;
 use jsonrpc_core::{
     futures::{Future, Sink},
     Result as JsonRpcResult,
@@ -68,6 +68,7 @@ impl IdProvider for HexEncodedIdProvider {
 pub struct EthPubSubApi<B: BlockT, P, C, BE, H: ExHashT> {
     _pool: Arc<P>,
     client: Arc<C>,
+    overrides: Arc<OverrideHandle<B>>,
     network: Arc<NetworkService<B, H>>,
     subscriptions: SubscriptionManager<HexEncodedIdProvider>,
     _marker: PhantomData<(B, BE)>,
@@ -77,12 +78,14 @@ impl<B: BlockT, P, C, BE, H: ExHashT> EthPubSubApi<B, P, C, BE, H> {
     pub fn new(
         _pool: Arc<P>,
         client: Arc<C>,
+        overrides: Arc<OverrideHandle<B>>,
         network: Arc<NetworkService<B, H>>,
         subscriptions: SubscriptionManager<HexEncodedIdProvider>,
     ) -> Self {
         Self {
             _pool,
             client,
+            overrides,
             network,
             subscriptions,
             _marker: PhantomData,
@@ -208,6 +211,45 @@ fn storage_prefix_build(module: &[u8], storage: &[u8]) -> Vec<u8> {
     [twox_128(module), twox_128(storage)].concat().to_vec()
 }
 
+/// Ethereum block of `hash`, from the client-side store or else the block's own storage layout.
+fn current_block<B, C>(
+    client: &Arc<C>,
+    overrides: &OverrideHandle<B>,
+    hash: B::Hash,
+) -> Option<ethereum::Block>
+    where
+        B: BlockT,
+        C: ProvideRuntimeApi<B> + HeaderBackend<B> + AuxStore,
+        C::Api: EthereumRuntimeRPCApi<B>,
+{
+    stored(ac_consensus::ethereum_block_hash::<B, _>(client.as_ref(), hash))
+        .and_then(|hash| stored(ac_consensus::load_ethereum_block(client.as_ref(), hash)))
+        .or_else(|| {
+            let id = BlockId::Hash(hash);
+            overrides.for_block(client.as_ref(), &id).current_block(&id)
+        })
+}
+
+fn current_receipts<B, C>(
+    client: &Arc<C>,
+    overrides: &OverrideHandle<B>,
+    hash: B::Hash,
+) -> Option<Vec<ethereum::Receipt>>
+    where
+        B: BlockT,
+        C: ProvideRuntimeApi<B> + HeaderBackend<B> + AuxStore,
+        C::Api: EthereumRuntimeRPCApi<B>,
+{
+    stored(ac_consensus::ethereum_block_hash::<B, _>(client.as_ref(), hash))
+        .and_then(|hash| stored(ac_consensus::load_ethereum_receipts(client.as_ref(), hash)))
+        .or_else(|| {
+            let id = BlockId::Hash(hash);
+            overrides
+                .for_block(client.as_ref(), &id)
+                .current_receipts(&id)
+        })
+}
+
 macro_rules! stream_build {
     ($context:expr => $module:expr, $storage:expr) => {{
         let key: StorageKey = StorageKey(storage_prefix_build($module, $storage));
@@ -246,6 +288,7 @@ impl<B: BlockT, P, C, BE, H: ExHashT> EthPubSubApiT for EthPubSubApi<B, P, C, BE
         };
 
         let client = self.client.clone();
+        let overrides = self.overrides.clone();
         let network = self.network.clone();
         match kind {
             Kind::Logs => {
@@ -254,27 +297,10 @@ impl<B: BlockT, P, C, BE, H: ExHashT> EthPubSubApiT for EthPubSubApi<B, P, C, BE
                 ) {
                     self.subscriptions.add(subscriber, |sink| {
                         let stream = stream
-                            .flat_map(move |(block_hash, changes)| {
-                                let id = BlockId::Hash(block_hash);
-                                let data = changes.iter().last().unwrap().2.unwrap();
-                                let receipts: Vec<ethereum::Receipt> =
-                                    Decode::decode(&mut &data.0[..]).unwrap();
-                                let block: Option<ethereum::Block> = if let Ok(Some(data)) = client
-                                    .storage(
-                                        &id,
-                                        &StorageKey(storage_prefix_build(
-                                            b"Ethereum",
-                                            b"CurrentBlock",
-                                        )),
-                                    ) {
-                                    if let Ok(result) = Decode::decode(&mut &data.0[..]) {
-                                        Some(result)
-                                    } else {
-                                        None
-                                    }
-                                } else {
-                                    None
-                                };
+                            .flat_map(move |(block_hash, _changes)| {
+                                let block = current_block(&client, &overrides, block_hash);
+                                let receipts = current_receipts(&client, &overrides, block_hash)
+                                    .unwrap_or_default();
                                 futures::stream::iter(SubscriptionResult::new().logs(
                                     block,
                                     receipts,
@@ -303,10 +329,12 @@ impl<B: BlockT, P, C, BE, H: ExHashT> EthPubSubApiT for EthPubSubApi<B, P, C, BE
                 ) {
                     self.subscriptions.add(subscriber, |sink| {
                         let stream = stream
-                            .map(|(_block, changes)| {
-                                let data = changes.iter().last().unwrap().2.unwrap();
-                                let block: ethereum::Block =
-                                    Decode::decode(&mut &data.0[..]).unwrap();
+                            .filter_map(move |(block_hash, _changes)| {
+                                futures::future::ready(current_block(
+                                    &client, &overrides, block_hash,
+                                ))
+                            })
+                            .map(|block| {
                                 return Ok::<_, ()>(Ok(SubscriptionResult::new().new_heads(block)));
                             })
                             .compat();
@@ -319,34 +347,14 @@ impl<B: BlockT, P, C, BE, H: ExHashT> EthPubSubApiT for EthPubSubApi<B, P, C, BE
             }
             Kind::NewPendingTransactions => {
                 if let Some(stream) = stream_build!(
-                    self => b"Ethereum", b"Pending"
+                    self => b"Ethereum", b"CurrentBlock"
                 ) {
                     self.subscriptions.add(subscriber, |sink| {
                         let stream = stream
-                            .flat_map(|(_block, changes)| {
-                                let mut transactions: Vec<ethereum::Transaction> = vec![];
-                                let storage: Vec<Option<StorageData>> = changes
-                                    .iter()
-                                    .filter_map(|(o_sk, _k, v)| {
-                                        if o_sk.is_none() {
-                                            Some(v.cloned())
-                                        } else {
-                                            None
-                                        }
-                                    })
-                                    .collect();
-                                for change in storage {
-                                    if let Some(data) = change {
-                                        let storage: Vec<(
-                                            ethereum::Transaction,
-                                            TransactionStatus,
-                                            ethereum::Receipt,
-                                        )> = Decode::decode(&mut &data.0[..]).unwrap();
-                                        let tmp: Vec<ethereum::Transaction> =
-                                            storage.iter().map(|x| x.0.clone()).collect();
-                                        transactions.extend(tmp);
-                                    }
-                                }
+                            .flat_map(move |(block_hash, _changes)| {
+                                let transactions = current_block(&client, &overrides, block_hash)
+                                    .map(|block| block.transactions)
+                                    .unwrap_or_default();
                                 futures::stream::iter(transactions)
                             })
                             .map(|transaction| {
@@ -0,0 +1,525 @@
+use crate::{internal_err, secret_key_address, sign_hash, sign_transaction_message, EthSigner};
+use ctr::cipher::stream::{NewStreamCipher, SyncStreamCipher};
+use ethereum_types::{H160, H256};
+use hmac::Hmac;
+use jsonrpc_core::Error;
+use log::warn;
+use rand::{thread_rng, Rng};
+use rustc_hex::{FromHex, ToHex};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+use std::collections::BTreeMap;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fs, io, sync::RwLock};
+use zeroize::Zeroizing;
+
+type Aes128Ctr = ctr::Ctr128<aes::Aes128>;
+
+/// Length of the derived key: an AES-128 key followed by the MAC key.
+const DERIVED_KEY_LENGTH: usize = 32;
+
+/// Largest scrypt `n` accepted from a key file, the one geth uses for standard key files.
+const MAX_SCRYPT_N: u32 = 1 << 18;
+
+/// Largest scrypt `n * r * p` accepted from a key file, which bounds the work of a derivation.
+const MAX_SCRYPT_COST: u64 = 1 << 21;
+
+/// Largest PBKDF2 iteration count accepted from a key file.
+const MAX_PBKDF2_ROUNDS: u32 = 1 << 20;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct KeyFile {
+    address: String,
+    #[serde(alias = "Crypto")]
+    crypto: KeyCrypto,
+    id: String,
+    version: u8,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct KeyCrypto {
+    cipher: String,
+    cipherparams: CipherParams,
+    ciphertext: String,
+    kdf: String,
+    kdfparams: KdfParams,
+    mac: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CipherParams {
+    iv: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum KdfParams {
+    Scrypt {
+        dklen: usize,
+        n: u32,
+        r: u32,
+        p: u32,
+        salt: String,
+    },
+    Pbkdf2 {
+        dklen: usize,
+        c: u32,
+        prf: String,
+        salt: String,
+    },
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, Error> {
+    value
+        .trim_start_matches("0x")
+        .from_hex()
+        .map_err(|_| internal_err("invalid hex in key file"))
+}
+
+impl KeyFile {
+    fn encrypt(secret: &secp256k1::SecretKey, passphrase: &str) -> Result<Self, Error> {
+        let mut rng = thread_rng();
+        let salt: [u8; 32] = rng.gen();
+        let iv: [u8; 16] = rng.gen();
+        let mut id: [u8; 16] = rng.gen();
+        // Random (version 4) UUID.
+        id[6] = (id[6] & 0x0f) | 0x40;
+        id[8] = (id[8] & 0x3f) | 0x80;
+        let id: String = id.to_hex();
+        let mut key_file = KeyFile {
+            address: secret_key_address(secret).as_bytes().to_hex(),
+            crypto: KeyCrypto {
+                cipher: "aes-128-ctr".to_string(),
+                cipherparams: CipherParams { iv: iv.to_hex() },
+                ciphertext: String::new(),
+                kdf: "scrypt".to_string(),
+                kdfparams: KdfParams::Scrypt {
+                    dklen: DERIVED_KEY_LENGTH,
+                    n: MAX_SCRYPT_N,
+                    r: 8,
+                    p: 1,
+                    salt: salt.to_hex(),
+                },
+                mac: String::new(),
+            },
+            id: format!(
+                "{}-{}-{}-{}-{}",
+                &id[0..8],
+                &id[8..12],
+                &id[12..16],
+                &id[16..20],
+                &id[20..32]
+            ),
+            version: 3,
+        };
+        let derived = key_file.derive_key(passphrase)?;
+        let mut data = Zeroizing::new(secret.serialize()).to_vec();
+        let mut cipher = Aes128Ctr::new_var(&derived[0..16], &iv)
+            .map_err(|_| internal_err("invalid cipher parameters"))?;
+        cipher.apply_keystream(&mut data);
+        let mac = Keccak256::digest(&Zeroizing::new([&derived[16..32], &data[..]].concat()));
+        key_file.crypto.ciphertext = data.to_hex();
+        key_file.crypto.mac = mac.as_slice().to_hex();
+        Ok(key_file)
+    }
+
+    fn address(&self) -> Option<H160> {
+        let bytes = decode_hex(&self.address).ok()?;
+        if bytes.len() != 20 {
+            return None;
+        }
+        Some(H160::from_slice(&bytes))
+    }
+
+    /// The key file is untrusted, so its parameters are bounded before any work is done.
+    fn derive_key(&self, passphrase: &str) -> Result<Zeroizing<Vec<u8>>, Error> {
+        match (self.crypto.kdf.as_str(), &self.crypto.kdfparams) {
+            (
+                "scrypt",
+                KdfParams::Scrypt {
+                    dklen,
+                    n,
+                    r,
+                    p,
+                    salt,
+                },
+            ) => {
+                let cost = (*n as u64)
+                    .saturating_mul(*r as u64)
+                    .saturating_mul(*p as u64);
+                if *dklen != DERIVED_KEY_LENGTH
+                    || !n.is_power_of_two()
+                    || *n < 2
+                    || *n > MAX_SCRYPT_N
+                    || *r == 0
+                    || *p == 0
+                    || cost > MAX_SCRYPT_COST
+                {
+                    return Err(internal_err("invalid scrypt parameters in key file"));
+                }
+                let params = scrypt::ScryptParams::new(n.trailing_zeros() as u8, *r, *p)
+                    .map_err(|_| internal_err("invalid scrypt parameters in key file"))?;
+                let mut derived = Zeroizing::new(vec![0u8; DERIVED_KEY_LENGTH]);
+                scrypt::scrypt(
+                    passphrase.as_bytes(),
+                    &decode_hex(salt)?,
+                    &params,
+                    &mut derived,
+                )
+                .map_err(|_| internal_err("invalid scrypt parameters in key file"))?;
+                Ok(derived)
+            }
+            ("pbkdf2", KdfParams::Pbkdf2 { dklen, c, prf, salt }) => {
+                if prf != "hmac-sha256" {
+                    return Err(internal_err(format!("unsupported pbkdf2 prf: {}", prf)));
+                }
+                if *dklen != DERIVED_KEY_LENGTH || *c == 0 || *c > MAX_PBKDF2_ROUNDS {
+                    return Err(internal_err("invalid pbkdf2 parameters in key file"));
+                }
+                let mut derived = Zeroizing::new(vec![0u8; DERIVED_KEY_LENGTH]);
+                pbkdf2::pbkdf2::<Hmac<Sha256>>(
+                    passphrase.as_bytes(),
+                    &decode_hex(salt)?,
+                    *c,
+                    &mut derived,
+                );
+                Ok(derived)
+            }
+            (kdf, _) => Err(internal_err(format!("unsupported key derivation: {}", kdf))),
+        }
+    }
+
+    fn decrypt(&self, passphrase: &str) -> Result<secp256k1::SecretKey, Error> {
+        if self.crypto.cipher != "aes-128-ctr" {
+            return Err(internal_err(format!(
+                "unsupported cipher: {}",
+                self.crypto.cipher
+            )));
+        }
+        let derived = self.derive_key(passphrase)?;
+        let mut data = Zeroizing::new(decode_hex(&self.crypto.ciphertext)?);
+        let mac = Keccak256::digest(&Zeroizing::new([&derived[16..32], &data[..]].concat()));
+        if mac.as_slice() != &decode_hex(&self.crypto.mac)?[..] {
+            return Err(internal_err("could not decrypt key with given password"));
+        }
+        let mut cipher =
+            Aes128Ctr::new_var(&derived[0..16], &decode_hex(&self.crypto.cipherparams.iv)?)
+                .map_err(|_| internal_err("invalid cipher parameters in key file"))?;
+        cipher.apply_keystream(&mut data);
+        secp256k1::SecretKey::parse_slice(&data)
+            .map_err(|_| internal_err("key file contains an invalid secret key"))
+    }
+}
+
+/// Signer backed by a directory of Web3 Secret Storage (V3) key files. Keys stay encrypted
+/// until unlocked with their passphrase.
+pub struct EthKeystoreSigner {
+    path: PathBuf,
+    keys: RwLock<BTreeMap<H160, KeyFile>>,
+    unlocked: RwLock<BTreeMap<H160, (secp256k1::SecretKey, Option<Instant>)>>,
+}
+
+impl EthKeystoreSigner {
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        fs::create_dir_all(&path)?;
+        let mut keys = BTreeMap::new();
+        for entry in fs::read_dir(&path)? {
+            let file = entry?.path();
+            if !file.is_file() {
+                continue;
+            }
+            let key_file = fs::read(&file)
+                .ok()
+                .and_then(|content| serde_json::from_slice::<KeyFile>(&content).ok());
+            match key_file
+                .filter(|key_file| key_file.version == 3)
+                .and_then(|key_file| key_file.address().map(|address| (address, key_file)))
+            {
+                Some((address, key_file)) => {
+                    keys.insert(address, key_file);
+                }
+                None => warn!("Skipping invalid key file {:?}", file),
+            }
+        }
+        Ok(Self {
+            path,
+            keys: RwLock::new(keys),
+            unlocked: RwLock::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn key_file(&self, address: &H160) -> Result<KeyFile, Error> {
+        self.keys
+            .read()
+            .map_err(|_| internal_err("keystore is not available"))?
+            .get(address)
+            .cloned()
+            .ok_or(internal_err("unknown account"))
+    }
+
+    fn decrypt(&self, address: &H160, passphrase: &str) -> Result<secp256k1::SecretKey, Error> {
+        let secret = self.key_file(address)?.decrypt(passphrase)?;
+        if &secret_key_address(&secret) != address {
+            return Err(internal_err("key file address does not match its key"));
+        }
+        Ok(secret)
+    }
+
+    fn unlocked_key(&self, address: &H160) -> Result<secp256k1::SecretKey, Error> {
+        let mut unlocked = self
+            .unlocked
+            .write()
+            .map_err(|_| internal_err("keystore is not available"))?;
+        match unlocked.get(address).cloned() {
+            Some((secret, None)) => Ok(secret),
+            Some((secret, Some(until))) if until > Instant::now() => Ok(secret),
+            Some(_) => {
+                unlocked.remove(address);
+                Err(internal_err("authentication needed: password or unlock"))
+            }
+            None => Err(internal_err("authentication needed: password or unlock")),
+        }
+    }
+
+    fn store(&self, secret: &secp256k1::SecretKey, passphrase: &str) -> Result<H160, Error> {
+        let address = secret_key_address(secret);
+        if self.key_file(&address).is_ok() {
+            return Err(internal_err("account already exists"));
+        }
+        // Key derivation is slow, so it runs before taking the lock every signer needs.
+        let key_file = KeyFile::encrypt(secret, passphrase)?;
+        let mut keys = self
+            .keys
+            .write()
+            .map_err(|_| internal_err("keystore is not available"))?;
+        if keys.contains_key(&address) {
+            return Err(internal_err("account already exists"));
+        }
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let file = self
+            .path
+            .join(format!("UTC--{}--{}", created, key_file.address));
+        let content = serde_json::to_vec(&key_file)
+            .map_err(|err| internal_err(format!("encode key file failed: {:?}", err)))?;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options
+            .open(&file)
+            .and_then(|mut file| file.write_all(&content))
+            .map_err(|err| internal_err(format!("write key file failed: {:?}", err)))?;
+        keys.insert(address, key_file);
+        Ok(address)
+    }
+}
+
+impl EthSigner for EthKeystoreSigner {
+    fn accounts(&self) -> Vec<H160> {
+        match self.keys.read() {
+            Ok(keys) => keys.keys().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn sign(
+        &self,
+        message: ethereum::TransactionMessage,
+        address: &H160,
+    ) -> Result<ethereum::Transaction, Error> {
+        sign_transaction_message(&self.unlocked_key(address)?, message)
+    }
+
+    fn new_account(&self, passphrase: &str) -> Result<H160, Error> {
+        let secret = secp256k1::SecretKey::random(&mut thread_rng());
+        self.store(&secret, passphrase)
+    }
+
+    fn import_raw_key(&self, secret: &[u8], passphrase: &str) -> Result<H160, Error> {
+        let secret = secp256k1::SecretKey::parse_slice(secret)
+            .map_err(|_| internal_err("invalid secret key"))?;
+        self.store(&secret, passphrase)
+    }
+
+    fn unlock(
+        &self,
+        address: &H160,
+        passphrase: &str,
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        let until = match duration {
+            Some(duration) => Some(
+                Instant::now()
+                    .checked_add(duration)
+                    .ok_or_else(|| internal_err("unlock duration too large"))?,
+            ),
+            None => None,
+        };
+        let secret = self.decrypt(address, passphrase)?;
+        self.unlocked
+            .write()
+            .map_err(|_| internal_err("keystore is not available"))?
+            .insert(*address, (secret, until));
+        Ok(())
+    }
+
+    fn lock(&self, address: &H160) -> Result<(), Error> {
+        self.unlocked
+            .write()
+            .map_err(|_| internal_err("keystore is not available"))?
+            .remove(address);
+        Ok(())
+    }
+
+    fn sign_with_passphrase(
+        &self,
+        message: ethereum::TransactionMessage,
+        address: &H160,
+        passphrase: &str,
+    ) -> Result<ethereum::Transaction, Error> {
+        let secret = self.decrypt(address, passphrase)?;
+        sign_transaction_message(&secret, message)
+    }
+
+    fn sign_hash(&self, address: &H160, hash: H256) -> Result<[u8; 65], Error> {
+        sign_hash(&self.unlocked_key(address)?, hash)
+    }
+
+    fn sign_hash_with_passphrase(
+        &self,
+        address: &H160,
+        hash: H256,
+        passphrase: &str,
+    ) -> Result<[u8; 65], Error> {
+        sign_hash(&self.decrypt(address, passphrase)?, hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test vectors of the Web3 Secret Storage definition, with password "testpassword".
+    const SPEC_SECRET: &str = "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d";
+
+    const SPEC_PBKDF2: &str = r#"{
+        "address": "",
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "6087dab2f9fdbbfaddc31a909735c1e6" },
+            "ciphertext": "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+            "kdf": "pbkdf2",
+            "kdfparams": {
+                "c": 262144,
+                "dklen": 32,
+                "prf": "hmac-sha256",
+                "salt": "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+            },
+            "mac": "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+
+    const SPEC_SCRYPT: &str = r#"{
+        "address": "",
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "83dbcc02d8ccb40e466191a123791e0e" },
+            "ciphertext": "d172bf743a674da9cdad04534d56926ef8358534d458fffccd4e6ad2fbde479c",
+            "kdf": "scrypt",
+            "kdfparams": {
+                "dklen": 32,
+                "n": 262144,
+                "p": 8,
+                "r": 1,
+                "salt": "ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"
+            },
+            "mac": "2103ac29920d71da29f15d75b4a16dbe95cfd7ff8faea1056c33131d846e3097"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+
+    fn spec_secret() -> secp256k1::SecretKey {
+        secp256k1::SecretKey::parse_slice(&SPEC_SECRET.from_hex::<Vec<u8>>().unwrap()).unwrap()
+    }
+
+    fn key_file(json: &str) -> KeyFile {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn decrypts_pbkdf2_spec_vector() {
+        let key_file = key_file(SPEC_PBKDF2);
+        assert_eq!(key_file.decrypt("testpassword").unwrap(), spec_secret());
+        assert!(key_file.decrypt("wrongpassword").is_err());
+    }
+
+    #[test]
+    fn decrypts_scrypt_spec_vector() {
+        let key_file = key_file(SPEC_SCRYPT);
+        assert_eq!(key_file.decrypt("testpassword").unwrap(), spec_secret());
+        assert!(key_file.decrypt("wrongpassword").is_err());
+    }
+
+    #[test]
+    fn encrypt_decrypt_round_trip() {
+        let secret = spec_secret();
+        let key_file = KeyFile::encrypt(&secret, "passphrase").unwrap();
+        assert_eq!(key_file.address(), Some(secret_key_address(&secret)));
+        assert_eq!(key_file.decrypt("passphrase").unwrap(), secret);
+        assert!(key_file.decrypt("testpassword").is_err());
+    }
+
+    #[test]
+    fn rejects_unbounded_kdf_parameters() {
+        let scrypt = |n: u32, r: u32, p: u32, dklen: usize| {
+            let mut key_file = key_file(SPEC_SCRYPT);
+            if let KdfParams::Scrypt { salt, .. } = key_file.crypto.kdfparams.clone() {
+                key_file.crypto.kdfparams = KdfParams::Scrypt {
+                    dklen,
+                    n,
+                    r,
+                    p,
+                    salt,
+                };
+            }
+            key_file.derive_key("testpassword").map(|_| ())
+        };
+        assert!(scrypt(1 << 20, 1, 1, 32).is_err());
+        assert!(scrypt(1 << 18, 8, 8, 32).is_err());
+        assert!(scrypt(1 << 18, 1, 0, 32).is_err());
+        assert!(scrypt(3, 1, 1, 32).is_err());
+        assert!(scrypt(1 << 10, 1, 1, 1 << 30).is_err());
+
+        let pbkdf2 = |c: u32, dklen: usize| {
+            let mut key_file = key_file(SPEC_PBKDF2);
+            if let KdfParams::Pbkdf2 { prf, salt, .. } = key_file.crypto.kdfparams.clone() {
+                key_file.crypto.kdfparams = KdfParams::Pbkdf2 {
+                    dklen,
+                    c,
+                    prf,
+                    salt,
+                };
+            }
+            key_file.derive_key("testpassword").map(|_| ())
+        };
+        assert!(pbkdf2(u32::max_value(), 32).is_err());
+        assert!(pbkdf2(0, 32).is_err());
+        assert!(pbkdf2(1, 1 << 30).is_err());
+    }
+}
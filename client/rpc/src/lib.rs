@@ -1,12 +1,27 @@
+mod eip712;
 mod eth;
 mod eth_pubsub;
+mod keystore;
+mod overrides;
+mod personal;
+mod txpool;
 
+pub use eip712::hash_typed_data;
 pub use eth::{
-    EthApi, EthApiServer, EthFilterApi, EthFilterApiServer, NetApi, NetApiServer, Web3Api,
-    Web3ApiServer,
+    EthApi, EthApiServer, EthFilterApi, EthFilterApiServer, LocalNonces, NetApi, NetApiServer,
+    Web3Api, Web3ApiServer, DEFAULT_MAX_CONCURRENT_EXECUTIONS, DEFAULT_RPC_GAS_CAP,
+    PRICE_BUMP_PERCENT,
 };
 pub use eth_pubsub::{EthPubSubApi, EthPubSubApiServer, HexEncodedIdProvider};
+pub use keystore::EthKeystoreSigner;
+pub use overrides::{
+    overrides_handle, OverrideHandle, RuntimeApiStorageOverride, SchemaV1Override,
+    StorageOverride,
+};
+pub use personal::{PersonalApi, PersonalApiServer};
+pub use txpool::{TxPoolApi, TxPoolApiServer};
 
+use ac_rpc_core::types::TypedData;
 use ethereum::{
     Transaction as EthereumTransaction, TransactionMessage as EthereumTransactionMessage,
 };
@@ -15,6 +30,7 @@ use jsonrpc_core::{Error, ErrorCode, Value};
 use pallet_evm::ExitReason;
 use rustc_hex::ToHex;
 use sha3::{Digest, Keccak256};
+use std::{sync::Arc, time::Duration};
 
 pub fn internal_err<T: ToString>(message: T) -> Error {
     Error {
@@ -73,6 +89,126 @@ pub trait EthSigner: Send + Sync {
         message: ethereum::TransactionMessage,
         address: &H160,
     ) -> Result<ethereum::Transaction, Error>;
+
+    fn new_account(&self, _passphrase: &str) -> Result<H160, Error> {
+        Err(internal_err("signer does not support creating accounts"))
+    }
+
+    fn import_raw_key(&self, _secret: &[u8], _passphrase: &str) -> Result<H160, Error> {
+        Err(internal_err("signer does not support importing keys"))
+    }
+
+    /// Unlock `address` for `duration`, or until explicitly locked when `None`.
+    fn unlock(
+        &self,
+        _address: &H160,
+        _passphrase: &str,
+        _duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        Err(internal_err("signer does not support unlocking accounts"))
+    }
+
+    fn lock(&self, _address: &H160) -> Result<(), Error> {
+        Err(internal_err("signer does not support locking accounts"))
+    }
+
+    /// Sign with a key that is only unlocked for this single message.
+    fn sign_with_passphrase(
+        &self,
+        _message: ethereum::TransactionMessage,
+        _address: &H160,
+        _passphrase: &str,
+    ) -> Result<ethereum::Transaction, Error> {
+        Err(internal_err("signer does not support passphrase signing"))
+    }
+
+    /// Sign a 32-byte hash, returning the 65-byte `r ‖ s ‖ v` signature with `v` in {27, 28}.
+    fn sign_hash(&self, _address: &H160, _hash: H256) -> Result<[u8; 65], Error> {
+        Err(internal_err("signer does not support signing hashes"))
+    }
+
+    fn sign_hash_with_passphrase(
+        &self,
+        _address: &H160,
+        _hash: H256,
+        _passphrase: &str,
+    ) -> Result<[u8; 65], Error> {
+        Err(internal_err("signer does not support passphrase signing"))
+    }
+
+    /// Sign `message` with the EIP-191 `personal_sign` prefix.
+    fn sign_message(&self, address: &H160, message: &[u8]) -> Result<[u8; 65], Error> {
+        self.sign_hash(address, personal_message_hash(message))
+    }
+
+    fn sign_typed_data(&self, address: &H160, typed_data: &TypedData) -> Result<[u8; 65], Error> {
+        self.sign_hash(address, hash_typed_data(typed_data)?)
+    }
+}
+
+impl<S: EthSigner + ?Sized> EthSigner for Arc<S> {
+    fn accounts(&self) -> Vec<H160> {
+        (**self).accounts()
+    }
+
+    fn sign(
+        &self,
+        message: ethereum::TransactionMessage,
+        address: &H160,
+    ) -> Result<ethereum::Transaction, Error> {
+        (**self).sign(message, address)
+    }
+
+    fn new_account(&self, passphrase: &str) -> Result<H160, Error> {
+        (**self).new_account(passphrase)
+    }
+
+    fn import_raw_key(&self, secret: &[u8], passphrase: &str) -> Result<H160, Error> {
+        (**self).import_raw_key(secret, passphrase)
+    }
+
+    fn unlock(
+        &self,
+        address: &H160,
+        passphrase: &str,
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        (**self).unlock(address, passphrase, duration)
+    }
+
+    fn lock(&self, address: &H160) -> Result<(), Error> {
+        (**self).lock(address)
+    }
+
+    fn sign_with_passphrase(
+        &self,
+        message: ethereum::TransactionMessage,
+        address: &H160,
+        passphrase: &str,
+    ) -> Result<ethereum::Transaction, Error> {
+        (**self).sign_with_passphrase(message, address, passphrase)
+    }
+
+    fn sign_hash(&self, address: &H160, hash: H256) -> Result<[u8; 65], Error> {
+        (**self).sign_hash(address, hash)
+    }
+
+    fn sign_hash_with_passphrase(
+        &self,
+        address: &H160,
+        hash: H256,
+        passphrase: &str,
+    ) -> Result<[u8; 65], Error> {
+        (**self).sign_hash_with_passphrase(address, hash, passphrase)
+    }
+
+    fn sign_message(&self, address: &H160, message: &[u8]) -> Result<[u8; 65], Error> {
+        (**self).sign_message(address, message)
+    }
+
+    fn sign_typed_data(&self, address: &H160, typed_data: &TypedData) -> Result<[u8; 65], Error> {
+        (**self).sign_typed_data(address, typed_data)
+    }
 }
 
 pub struct EthDevSigner {
@@ -94,15 +230,7 @@ impl EthDevSigner {
 
 impl EthSigner for EthDevSigner {
     fn accounts(&self) -> Vec<H160> {
-        self.keys
-            .iter()
-            .map(|secret| {
-                let public = secp256k1::PublicKey::from_secret_key(secret);
-                let mut res = [0u8; 64];
-                res.copy_from_slice(&public.serialize()[1..65]);
-                H160::from(H256::from_slice(Keccak256::digest(&res).as_slice()))
-            })
-            .collect()
+        self.keys.iter().map(secret_key_address).collect()
     }
 
     fn sign(
@@ -110,38 +238,101 @@ impl EthSigner for EthDevSigner {
         message: ethereum::TransactionMessage,
         address: &H160,
     ) -> Result<ethereum::Transaction, Error> {
-        let mut transaction = None;
         for secret in &self.keys {
-            let key_address = {
-                let public = secp256k1::PublicKey::from_secret_key(secret);
-                let mut res = [0u8; 64];
-                res.copy_from_slice(&public.serialize()[1..65]);
-                H160::from(H256::from_slice(Keccak256::digest(&res).as_slice()))
-            };
-            if &key_address == address {
-                let signing_message = secp256k1::Message::parse_slice(&message.hash()[..])
-                    .map_err(|_| internal_err("invalid signing message"))?;
-                let (signature, recid) = secp256k1::sign(&signing_message, secret);
-                let v = match message.chain_id {
-                    None => 27 + recid.serialize() as u64,
-                    Some(chain_id) => 2 * chain_id + 35 + recid.serialize() as u64,
-                };
-                let rs = signature.serialize();
-                let r = H256::from_slice(&rs[0..32]);
-                let s = H256::from_slice(&rs[32..64]);
-                transaction = Some(ethereum::Transaction {
-                    nonce: message.nonce,
-                    gas_price: message.gas_price,
-                    gas_limit: message.gas_limit,
-                    action: message.action,
-                    value: message.value,
-                    input: message.input.clone(),
-                    signature: ethereum::TransactionSignature::new(v, r, s)
-                        .ok_or(internal_err("signer generated invalid signature"))?,
-                });
-                break;
+            if &secret_key_address(secret) == address {
+                return sign_transaction_message(secret, message);
+            }
+        }
+        Err(internal_err("signer not available"))
+    }
+
+    fn unlock(
+        &self,
+        address: &H160,
+        _passphrase: &str,
+        _duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        // Development keys are always unlocked.
+        if self.accounts().contains(address) {
+            Ok(())
+        } else {
+            Err(internal_err("signer not available"))
+        }
+    }
+
+    fn sign_with_passphrase(
+        &self,
+        message: ethereum::TransactionMessage,
+        address: &H160,
+        _passphrase: &str,
+    ) -> Result<ethereum::Transaction, Error> {
+        self.sign(message, address)
+    }
+
+    fn sign_hash(&self, address: &H160, hash: H256) -> Result<[u8; 65], Error> {
+        for secret in &self.keys {
+            if &secret_key_address(secret) == address {
+                return sign_hash(secret, hash);
             }
         }
-        transaction.ok_or(internal_err("signer not available"))
+        Err(internal_err("signer not available"))
+    }
+
+    fn sign_hash_with_passphrase(
+        &self,
+        address: &H160,
+        hash: H256,
+        _passphrase: &str,
+    ) -> Result<[u8; 65], Error> {
+        self.sign_hash(address, hash)
     }
+}
+
+pub fn secret_key_address(secret: &secp256k1::SecretKey) -> H160 {
+    let public = secp256k1::PublicKey::from_secret_key(secret);
+    let mut res = [0u8; 64];
+    res.copy_from_slice(&public.serialize()[1..65]);
+    H160::from(H256::from_slice(Keccak256::digest(&res).as_slice()))
+}
+
+pub fn personal_message_hash(message: &[u8]) -> H256 {
+    let mut prefixed = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    prefixed.extend_from_slice(message);
+    H256::from_slice(Keccak256::digest(&prefixed).as_slice())
+}
+
+pub fn sign_hash(secret: &secp256k1::SecretKey, hash: H256) -> Result<[u8; 65], Error> {
+    let signing_message = secp256k1::Message::parse_slice(hash.as_bytes())
+        .map_err(|_| internal_err("invalid signing message"))?;
+    let (signature, recid) = secp256k1::sign(&signing_message, secret);
+    let mut res = [0u8; 65];
+    res[0..64].copy_from_slice(&signature.serialize()[..]);
+    res[64] = 27 + recid.serialize();
+    Ok(res)
+}
+
+pub fn sign_transaction_message(
+    secret: &secp256k1::SecretKey,
+    message: ethereum::TransactionMessage,
+) -> Result<ethereum::Transaction, Error> {
+    let signing_message = secp256k1::Message::parse_slice(&message.hash()[..])
+        .map_err(|_| internal_err("invalid signing message"))?;
+    let (signature, recid) = secp256k1::sign(&signing_message, secret);
+    let v = match message.chain_id {
+        None => 27 + recid.serialize() as u64,
+        Some(chain_id) => 2 * chain_id + 35 + recid.serialize() as u64,
+    };
+    let rs = signature.serialize();
+    let r = H256::from_slice(&rs[0..32]);
+    let s = H256::from_slice(&rs[32..64]);
+    Ok(ethereum::Transaction {
+        nonce: message.nonce,
+        gas_price: message.gas_price,
+        gas_limit: message.gas_limit,
+        action: message.action,
+        value: message.value,
+        input: message.input.clone(),
+        signature: ethereum::TransactionSignature::new(v, r, s)
+            .ok_or(internal_err("signer generated invalid signature"))?,
+    })
 }
\ No newline at end of file
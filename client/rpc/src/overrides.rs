@@ -0,0 +1,222 @@
+use ap_rpc::{EthereumRuntimeRPCApi, TransactionStatus};
+use codec::Decode;
+use ethereum_types::{H160, H256, U256};
+use sc_client_api::backend::{Backend, StateBackend, StorageProvider};
+use sp_api::{ApiExt, BlockId, ProvideRuntimeApi};
+use sp_io::hashing::{blake2_128, twox_128};
+use sp_runtime::traits::{BlakeTwo256, Block as BlockT};
+use sp_storage::StorageKey;
+use std::{collections::BTreeMap, marker::PhantomData, sync::Arc};
+
+/// Reads the Ethereum data of a block as laid out by the runtime that produced it.
+pub trait StorageOverride<Block: BlockT>: Send + Sync {
+    fn account_code_at(&self, block: &BlockId<Block>, address: H160) -> Option<Vec<u8>>;
+    fn storage_at(&self, block: &BlockId<Block>, address: H160, index: U256) -> Option<H256>;
+    fn current_block(&self, block: &BlockId<Block>) -> Option<ethereum::Block>;
+    fn current_receipts(&self, block: &BlockId<Block>) -> Option<Vec<ethereum::Receipt>>;
+    fn current_transaction_statuses(
+        &self,
+        block: &BlockId<Block>,
+    ) -> Option<Vec<TransactionStatus>>;
+}
+
+/// Storage overrides keyed by the `EthereumRuntimeRPCApi` version of the runtime they read.
+/// Blocks whose version has no override are served by `fallback`.
+pub struct OverrideHandle<Block: BlockT> {
+    schemas: BTreeMap<u32, Box<dyn StorageOverride<Block>>>,
+    fallback: Box<dyn StorageOverride<Block>>,
+}
+
+impl<Block: BlockT> OverrideHandle<Block> {
+    pub fn new(
+        schemas: BTreeMap<u32, Box<dyn StorageOverride<Block>>>,
+        fallback: Box<dyn StorageOverride<Block>>,
+    ) -> Self {
+        Self { schemas, fallback }
+    }
+
+    /// The override matching the runtime at `block`.
+    pub fn for_block<C>(&self, client: &C, block: &BlockId<Block>) -> &dyn StorageOverride<Block>
+        where
+            C: ProvideRuntimeApi<Block>,
+            C::Api: EthereumRuntimeRPCApi<Block>,
+    {
+        client
+            .runtime_api()
+            .api_version::<dyn EthereumRuntimeRPCApi<Block>>(block)
+            .ok()
+            .flatten()
+            .and_then(|version| self.schemas.get(&version))
+            .unwrap_or(&self.fallback)
+            .as_ref()
+    }
+}
+
+/// Overrides for the storage layouts this crate knows, falling back to the runtime API.
+pub fn overrides_handle<B, C, BE>(client: Arc<C>) -> Arc<OverrideHandle<B>>
+    where
+        B: BlockT<Hash = H256> + Send + Sync + 'static,
+        C: ProvideRuntimeApi<B> + StorageProvider<B, BE> + Send + Sync + 'static,
+        C::Api: EthereumRuntimeRPCApi<B>,
+        BE: Backend<B> + 'static,
+        BE::State: StateBackend<BlakeTwo256>,
+{
+    let mut schemas: BTreeMap<u32, Box<dyn StorageOverride<B>>> = BTreeMap::new();
+    schemas.insert(1, Box::new(SchemaV1Override::new(client.clone())));
+    // Version 2 only changed runtime API signatures, the storage layout is unchanged.
+    schemas.insert(2, Box::new(SchemaV1Override::new(client.clone())));
+    Arc::new(OverrideHandle::new(
+        schemas,
+        Box::new(RuntimeApiStorageOverride::new(client)),
+    ))
+}
+
+fn storage_prefix_build(module: &[u8], storage: &[u8]) -> Vec<u8> {
+    [twox_128(module), twox_128(storage)].concat().to_vec()
+}
+
+fn blake2_128_extend(bytes: &[u8]) -> Vec<u8> {
+    let mut ext: Vec<u8> = blake2_128(bytes).to_vec();
+    ext.extend_from_slice(bytes);
+    ext
+}
+
+/// Reads raw storage of runtimes at `EthereumRuntimeRPCApi` version 1, without executing them.
+pub struct SchemaV1Override<B: BlockT, C, BE> {
+    client: Arc<C>,
+    _marker: PhantomData<(B, BE)>,
+}
+
+impl<B: BlockT, C, BE> SchemaV1Override<B, C, BE> {
+    pub fn new(client: Arc<C>) -> Self {
+        Self {
+            client,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<B, C, BE> SchemaV1Override<B, C, BE>
+    where
+        B: BlockT<Hash = H256> + Send + Sync + 'static,
+        C: StorageProvider<B, BE> + Send + Sync + 'static,
+        BE: Backend<B> + 'static,
+        BE::State: StateBackend<BlakeTwo256>,
+{
+    fn query_storage<T: Decode>(&self, id: &BlockId<B>, key: &StorageKey) -> Option<T> {
+        if let Ok(Some(data)) = self.client.storage(id, key) {
+            if let Ok(result) = Decode::decode(&mut &data.0[..]) {
+                return Some(result);
+            }
+        }
+        None
+    }
+}
+
+impl<B, C, BE> StorageOverride<B> for SchemaV1Override<B, C, BE>
+    where
+        B: BlockT<Hash = H256> + Send + Sync + 'static,
+        C: StorageProvider<B, BE> + Send + Sync + 'static,
+        BE: Backend<B> + 'static,
+        BE::State: StateBackend<BlakeTwo256>,
+{
+    fn account_code_at(&self, block: &BlockId<B>, address: H160) -> Option<Vec<u8>> {
+        let mut key: Vec<u8> = storage_prefix_build(b"EVM", b"AccountCodes");
+        key.extend(blake2_128_extend(address.as_bytes()));
+        self.query_storage::<Vec<u8>>(block, &StorageKey(key))
+    }
+
+    fn storage_at(&self, block: &BlockId<B>, address: H160, index: U256) -> Option<H256> {
+        let tmp: &mut [u8; 32] = &mut [0; 32];
+        index.to_little_endian(tmp);
+        let mut key: Vec<u8> = storage_prefix_build(b"EVM", b"AccountStorages");
+        key.extend(blake2_128_extend(address.as_bytes()));
+        key.extend(blake2_128_extend(tmp));
+        self.query_storage::<H256>(block, &StorageKey(key))
+    }
+
+    fn current_block(&self, block: &BlockId<B>) -> Option<ethereum::Block> {
+        self.query_storage::<ethereum::Block>(
+            block,
+            &StorageKey(storage_prefix_build(b"Ethereum", b"CurrentBlock")),
+        )
+    }
+
+    fn current_receipts(&self, block: &BlockId<B>) -> Option<Vec<ethereum::Receipt>> {
+        self.query_storage::<Vec<ethereum::Receipt>>(
+            block,
+            &StorageKey(storage_prefix_build(b"Ethereum", b"CurrentReceipts")),
+        )
+    }
+
+    fn current_transaction_statuses(&self, block: &BlockId<B>) -> Option<Vec<TransactionStatus>> {
+        self.query_storage::<Vec<TransactionStatus>>(
+            block,
+            &StorageKey(storage_prefix_build(
+                b"Ethereum",
+                b"CurrentTransactionStatuses",
+            )),
+        )
+    }
+}
+
+/// Asks the runtime itself, which always decodes its own storage correctly but needs the
+/// runtime to be executed.
+pub struct RuntimeApiStorageOverride<B: BlockT, C> {
+    client: Arc<C>,
+    _marker: PhantomData<B>,
+}
+
+impl<B: BlockT, C> RuntimeApiStorageOverride<B, C> {
+    pub fn new(client: Arc<C>) -> Self {
+        Self {
+            client,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<B, C> StorageOverride<B> for RuntimeApiStorageOverride<B, C>
+    where
+        B: BlockT<Hash = H256> + Send + Sync + 'static,
+        C: ProvideRuntimeApi<B> + Send + Sync + 'static,
+        C::Api: EthereumRuntimeRPCApi<B>,
+{
+    fn account_code_at(&self, block: &BlockId<B>, address: H160) -> Option<Vec<u8>> {
+        self.client
+            .runtime_api()
+            .account_code_at(block, address)
+            .ok()
+    }
+
+    fn storage_at(&self, block: &BlockId<B>, address: H160, index: U256) -> Option<H256> {
+        self.client
+            .runtime_api()
+            .storage_at(block, address, index)
+            .ok()
+    }
+
+    fn current_block(&self, block: &BlockId<B>) -> Option<ethereum::Block> {
+        self.client
+            .runtime_api()
+            .current_block(block)
+            .ok()
+            .flatten()
+    }
+
+    fn current_receipts(&self, block: &BlockId<B>) -> Option<Vec<ethereum::Receipt>> {
+        self.client
+            .runtime_api()
+            .current_receipts(block)
+            .ok()
+            .flatten()
+    }
+
+    fn current_transaction_statuses(&self, block: &BlockId<B>) -> Option<Vec<TransactionStatus>> {
+        self.client
+            .runtime_api()
+            .current_transaction_statuses(block)
+            .ok()
+            .flatten()
+    }
+}
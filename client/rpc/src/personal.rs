@@ -0,0 +1,139 @@
+use crate::{eth::EthApi, internal_err, personal_message_hash, EthSigner};
+use ac_rpc_core::types::{Bytes, TransactionRequest};
+use ac_rpc_core::{EthApi as EthApiT, PersonalApi as PersonalApiT};
+use ap_rpc::{ConvertTransaction, EthereumRuntimeRPCApi};
+use ethereum_types::{H160, H256};
+use jsonrpc_core::{futures::future, BoxFuture, Result};
+use rustc_hex::FromHex;
+use sc_client_api::backend::{AuxStore, Backend, StateBackend, StorageProvider};
+use sc_network::ExHashT;
+use sc_transaction_graph::ChainApi;
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::{Error as BlockChainError, HeaderBackend, HeaderMetadata};
+use sp_runtime::traits::{BlakeTwo256, Block as BlockT};
+use sp_transaction_pool::TransactionPool;
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+pub use ac_rpc_core::PersonalApiServer;
+
+const DEFAULT_UNLOCK_DURATION: u64 = 300;
+
+pub struct PersonalApi<B: BlockT, C, P, CT, BE, H: ExHashT, A: ChainApi> {
+    eth: EthApi<B, C, P, CT, BE, H, A>,
+}
+
+impl<B: BlockT, C, P, CT, BE, H: ExHashT, A: ChainApi> PersonalApi<B, C, P, CT, BE, H, A> {
+    pub fn new(eth: EthApi<B, C, P, CT, BE, H, A>) -> Self {
+        Self { eth }
+    }
+}
+
+impl<B, C, P, CT, BE, H: ExHashT, A> PersonalApi<B, C, P, CT, BE, H, A>
+    where
+        C: ProvideRuntimeApi<B> + StorageProvider<B, BE> + AuxStore,
+        C: HeaderBackend<B> + HeaderMetadata<B, Error = BlockChainError> + 'static,
+        C::Api: EthereumRuntimeRPCApi<B>,
+        BE: Backend<B> + 'static,
+        BE::State: StateBackend<BlakeTwo256>,
+        B: BlockT<Hash = H256> + Send + Sync + 'static,
+        C: Send + Sync + 'static,
+        P: TransactionPool<Block = B> + Send + Sync + 'static,
+        A: ChainApi<Block = B> + 'static,
+        CT: ConvertTransaction<<B as BlockT>::Extrinsic> + Send + Sync + 'static,
+{
+    /// Run `f` against the signers in order, returning the first success or the last error.
+    fn with_signers<R, F>(&self, address: Option<&H160>, f: F) -> Result<R>
+        where
+            F: Fn(&dyn EthSigner) -> Result<R>,
+    {
+        let mut error = internal_err("no signer available");
+        for signer in self.eth.signers() {
+            if let Some(address) = address {
+                if !signer.accounts().contains(address) {
+                    continue;
+                }
+            }
+            match f(signer.as_ref()) {
+                Ok(res) => return Ok(res),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+}
+
+impl<B, C, P, CT, BE, H: ExHashT, A> PersonalApiT for PersonalApi<B, C, P, CT, BE, H, A>
+    where
+        C: ProvideRuntimeApi<B> + StorageProvider<B, BE> + AuxStore,
+        C: HeaderBackend<B> + HeaderMetadata<B, Error = BlockChainError> + 'static,
+        C::Api: EthereumRuntimeRPCApi<B>,
+        BE: Backend<B> + 'static,
+        BE::State: StateBackend<BlakeTwo256>,
+        B: BlockT<Hash = H256> + Send + Sync + 'static,
+        C: Send + Sync + 'static,
+        P: TransactionPool<Block = B> + Send + Sync + 'static,
+        A: ChainApi<Block = B> + 'static,
+        CT: ConvertTransaction<<B as BlockT>::Extrinsic> + Send + Sync + 'static,
+{
+    fn list_accounts(&self) -> Result<Vec<H160>> {
+        self.eth.accounts()
+    }
+
+    fn new_account(&self, passphrase: String) -> Result<H160> {
+        self.with_signers(None, |signer| signer.new_account(&passphrase))
+    }
+
+    fn import_raw_key(&self, secret: String, passphrase: String) -> Result<H160> {
+        let secret: Zeroizing<Vec<u8>> = Zeroizing::new(
+            secret
+                .trim_start_matches("0x")
+                .from_hex()
+                .map_err(|_| internal_err("invalid hex secret key"))?,
+        );
+        self.with_signers(None, |signer| signer.import_raw_key(&secret, &passphrase))
+    }
+
+    fn unlock_account(
+        &self,
+        address: H160,
+        passphrase: String,
+        duration: Option<u64>,
+    ) -> Result<bool> {
+        let duration = match duration.unwrap_or(DEFAULT_UNLOCK_DURATION) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        self.with_signers(Some(&address), |signer| {
+            signer.unlock(&address, &passphrase, duration)
+        })
+        .map(|_| true)
+    }
+
+    fn lock_account(&self, address: H160) -> Result<bool> {
+        self.with_signers(Some(&address), |signer| signer.lock(&address))
+            .map(|_| true)
+    }
+
+    fn send_transaction(&self, request: TransactionRequest, passphrase: String) -> BoxFuture<H256> {
+        let (from, message, reservation) = match self.eth.transaction_message(request) {
+            Ok(res) => res,
+            Err(e) => return Box::new(future::result(Err(e))),
+        };
+        let transaction = self.with_signers(Some(&from), |signer| {
+            signer.sign_with_passphrase(message.clone(), &from, &passphrase)
+        });
+        match transaction {
+            Ok(transaction) => self.eth.submit_local_transaction(reservation, transaction),
+            Err(e) => Box::new(future::result(Err(e))),
+        }
+    }
+
+    fn sign(&self, data: Bytes, address: H160, passphrase: String) -> Result<Bytes> {
+        let hash = personal_message_hash(&data.0);
+        self.with_signers(Some(&address), |signer| {
+            signer.sign_hash_with_passphrase(&address, hash, &passphrase)
+        })
+        .map(|signature| Bytes(signature.to_vec()))
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{
+    eth::{require_api_version, transaction_build},
+    internal_err, public_key,
+};
+use ac_rpc_core::types::{
+    Summary, Transaction, TransactionMap, TransactionNonce, TxPoolContent, TxPoolInspect,
+    TxPoolResult, TxPoolStatus,
+};
+use ac_rpc_core::TxPoolApi as TxPoolApiT;
+use ap_rpc::EthereumRuntimeRPCApi;
+use ethereum_types::{H160, H256, U256};
+use jsonrpc_core::Result;
+use sc_transaction_graph::{ChainApi, Pool};
+use sha3::{Digest, Keccak256};
+use sp_api::{BlockId, ProvideRuntimeApi};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::Block as BlockT;
+use sp_transaction_pool::InPoolTransaction;
+use std::{marker::PhantomData, sync::Arc};
+
+pub use ac_rpc_core::TxPoolApiServer;
+
+pub struct TxPoolApi<B: BlockT, C, A: ChainApi> {
+    client: Arc<C>,
+    graph: Arc<Pool<A>>,
+    _marker: PhantomData<B>,
+}
+
+impl<B: BlockT, C, A: ChainApi> TxPoolApi<B, C, A> {
+    pub fn new(client: Arc<C>, graph: Arc<Pool<A>>) -> Self {
+        Self {
+            client,
+            graph,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<B, C, A> TxPoolApi<B, C, A>
+    where
+        B: BlockT<Hash = H256> + Send + Sync + 'static,
+        C: ProvideRuntimeApi<B> + HeaderBackend<B> + Send + Sync + 'static,
+        C::Api: EthereumRuntimeRPCApi<B>,
+        A: ChainApi<Block = B> + 'static,
+{
+    /// Ethereum transactions in the ready (pending) and future (queued) parts of the pool.
+    fn transactions(&self) -> Result<TxPoolResult<Vec<ethereum::Transaction>>> {
+        let ready = self
+            .graph
+            .validated_pool()
+            .ready()
+            .map(|tx| tx.data().clone())
+            .collect::<Vec<_>>();
+        let future = self
+            .graph
+            .validated_pool()
+            .futures()
+            .into_iter()
+            .map(|(_, xt)| xt)
+            .collect::<Vec<_>>();
+        let id = BlockId::Hash(self.client.info().best_hash);
+        require_api_version(self.client.as_ref(), &id, 2, "txpool inspection")?;
+        let api = self.client.runtime_api();
+        Ok(TxPoolResult {
+            pending: api.extrinsic_filter(&id, ready).map_err(|err| {
+                internal_err(format!("fetch ready transactions failed: {:?}", err))
+            })?,
+            queued: api.extrinsic_filter(&id, future).map_err(|err| {
+                internal_err(format!("fetch future transactions failed: {:?}", err))
+            })?,
+        })
+    }
+
+    fn group<T, F>(transactions: Vec<ethereum::Transaction>, f: F) -> TransactionMap<T>
+        where
+            F: Fn(&ethereum::Transaction) -> T,
+    {
+        let mut map = TransactionMap::new();
+        for transaction in transactions {
+            // The pool only admits transactions with a valid signature, so this should not happen.
+            let from = match public_key(&transaction) {
+                Ok(pk) => H160::from(H256::from_slice(Keccak256::digest(&pk).as_slice())),
+                Err(_) => continue,
+            };
+            map.entry(from)
+                .or_insert_with(Default::default)
+                .insert(TransactionNonce(transaction.nonce), f(&transaction));
+        }
+        map
+    }
+}
+
+impl<B, C, A> TxPoolApiT for TxPoolApi<B, C, A>
+    where
+        B: BlockT<Hash = H256> + Send + Sync + 'static,
+        C: ProvideRuntimeApi<B> + HeaderBackend<B> + Send + Sync + 'static,
+        C::Api: EthereumRuntimeRPCApi<B>,
+        A: ChainApi<Block = B> + 'static,
+{
+    fn status(&self) -> Result<TxPoolStatus> {
+        let transactions = self.transactions()?;
+        Ok(TxPoolStatus {
+            pending: U256::from(transactions.pending.len()),
+            queued: U256::from(transactions.queued.len()),
+        })
+    }
+
+    fn content(&self) -> Result<TxPoolContent> {
+        let transactions = self.transactions()?;
+        let build = |transaction: &ethereum::Transaction| -> Transaction {
+            transaction_build(transaction.clone(), None, None)
+        };
+        Ok(TxPoolContent {
+            pending: Self::group(transactions.pending, build),
+            queued: Self::group(transactions.queued, build),
+        })
+    }
+
+    fn inspect(&self) -> Result<TxPoolInspect> {
+        let transactions = self.transactions()?;
+        let summary = |transaction: &ethereum::Transaction| Summary {
+            to: match transaction.action {
+                ethereum::TransactionAction::Call(to) => Some(to),
+                ethereum::TransactionAction::Create => None,
+            },
+            value: transaction.value,
+            gas: transaction.gas_limit,
+            gas_price: transaction.gas_price,
+        };
+        Ok(TxPoolInspect {
+            pending: Self::group(transactions.pending, summary),
+            queued: Self::group(transactions.queued, summary),
+        })
+    }
+}
@@ -3,25 +3,31 @@
 use codec::{Decode, Encode};
 use ethereum_types::{Bloom, BloomInput, H160, H256, H64, U256};
 use evm::ExitReason;
-use ap_consensus::{ ConsensusLog, FRONTIER_ENGINE_ID };
-use ap_evm::CallOrCreateInfo;
+use ap_consensus::{ find_pre_block, ConsensusLog, FRONTIER_ENGINE_ID };
+use ap_evm::{CallOrCreateInfo, SimulateBlock};
 use frame_support::{
     decl_error, decl_event, decl_module, decl_storage, dispatch::DispatchResultWithPostInfo,
-    traits::FindAuthor, traits::Get, weights::Weight,
+    ensure, storage::migration, traits::FindAuthor, traits::Get, weights::Weight,
 };
 use frame_system::ensure_none;
-use pallet_evm::{GasWeightMapping, Runner};
+use pallet_evm::{FeeCalculator, GasWeightMapping, Runner};
 use sha3::{Digest, Keccak256};
 use sp_runtime::{
     generic::DigestItem,
     traits::UniqueSaturatedInto,
     transaction_validity::{
-        InvalidTransaction, TransactionSource, TransactionValidity, ValidTransactionBuilder,
+        InvalidTransaction, TransactionSource, TransactionValidity, TransactionValidityError,
+        ValidTransactionBuilder,
     },
     DispatchError,
 };
 use sp_std::prelude::*;
 
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+
 pub use ethereum::{Block, Log, Receipt, Transaction, TransactionAction, TransactionMessage};
 pub use ap_rpc::TransactionStatus;
 
@@ -52,18 +58,33 @@ pub trait Config:
     type FindAuthor: FindAuthor<H160>;
     type StateRoot: Get<H256>;
     type BlockGasLimit: Get<U256>;
+    /// Reject transactions that are not replay-protected with an EIP-155 chain id.
+    type RequireReplayProtection: Get<bool>;
+    /// Senders still allowed to submit transactions without a chain id, such as the one-time
+    /// keys of well-known deterministic deployment transactions.
+    type UnprotectedSenders: Get<Vec<H160>>;
+    /// Reject malleable signatures whose `s` value lies in the upper half of the curve order.
+    type RequireLowS: Get<bool>;
 }
 
 decl_storage! {
     trait Store for Module<T: Config> as Ethereum {
-        Pending: Vec<(ethereum::Transaction, TransactionStatus, ethereum::Receipt)>;
+        /// Number of Ethereum transactions executed so far in the current block.
+        PendingCount: u32;
+        PendingTransactions: map hasher(twox_64_concat) u32
+            => Option<(ethereum::Transaction, TransactionStatus, ethereum::Receipt)>;
+        /// Gas used by the Ethereum transactions included so far in the current block.
+        BlockGasUsed: U256;
         CurrentBlock: Option<ethereum::Block>;
         CurrentReceipts: Option<Vec<ethereum::Receipt>>;
         CurrentTransactionStatuses: Option<Vec<TransactionStatus>>;
+        /// Storage layout version, see `STORAGE_VERSION`.
+        StorageVersion: u32;
     }
     add_extra_genesis {
         build(|_config: &GenesisConfig| {
             <Module<T>>::store_block();
+            StorageVersion::put(STORAGE_VERSION);
         });
     }
 }
@@ -77,6 +98,10 @@ decl_event!(
 decl_error! {
     pub enum Error for Module<T: Config> {
         InvalidSignature,
+        BlockGasLimitExceeded,
+        InvalidChainId,
+        UnprotectedTransaction,
+        NonCanonicalSignature,
     }
 }
 
@@ -84,17 +109,28 @@ decl_module! {
     pub struct Module<T: Config> for enum Call where origin: T::Origin {
         fn deposit_event() = default;
 
-        #[weight = <T as pallet_evm::Config>::GasWeightMapping::gas_to_weight(transaction.gas_limit.unique_saturated_into())]
+        #[weight = <T as pallet_evm::Config>::GasWeightMapping::gas_to_weight(transaction.gas_limit.unique_saturated_into())
+            .saturating_add(<Module<T>>::transact_storage_weight())]
         fn transact(origin, transaction: ethereum::Transaction) -> DispatchResultWithPostInfo {
             ensure_none(origin)?;
 
             let source = Self::recover_signer(&transaction)
                 .ok_or_else(|| Error::<T>::InvalidSignature)?;
+            Self::validate_signature_policy(&transaction, &source).map_err(|e| match e {
+                TransactionValidationError::InvalidChainId => Error::<T>::InvalidChainId,
+                TransactionValidationError::UnprotectedTransaction => Error::<T>::UnprotectedTransaction,
+                TransactionValidationError::NonCanonicalSignature => Error::<T>::NonCanonicalSignature,
+                _ => Error::<T>::InvalidSignature,
+            })?;
+            ensure!(
+                transaction.gas_limit <= Self::remaining_block_gas(),
+                Error::<T>::BlockGasLimitExceeded
+            );
 
             let transaction_hash = H256::from_slice(
                 Keccak256::digest(&rlp::encode(&transaction)).as_slice()
             );
-            let transaction_index = Pending::get().len() as u32;
+            let transaction_index = PendingCount::get();
 
             let (to, contract_address, info) = Self::execute(
                 source,
@@ -158,56 +194,144 @@ decl_module! {
                 logs: status.clone().logs,
             };
 
-            Pending::append((transaction, status, receipt));
+            PendingTransactions::insert(transaction_index, (transaction, status, receipt));
+            PendingCount::put(transaction_index + 1);
+            BlockGasUsed::mutate(|gas_used| *gas_used = gas_used.saturating_add(used_gas));
 
             Self::deposit_event(Event::Executed(source, contract_address.unwrap_or_default(), transaction_hash, reason));
-            Ok(Some(T::GasWeightMapping::gas_to_weight(used_gas.unique_saturated_into())).into())
+            Ok(Some(
+                T::GasWeightMapping::gas_to_weight(used_gas.unique_saturated_into())
+                    .saturating_add(<Module<T>>::transact_storage_weight())
+            ).into())
         }
 
         fn on_finalize(n: T::BlockNumber) {
             <Module<T>>::store_block();
+            BlockGasUsed::kill();
         }
 
         fn on_initialize(n: T::BlockNumber) -> Weight {
-            Pending::kill();
-            0
+            // Same rule as block import, so an author cannot get a block with an ambiguous
+            // `PreBlock` log accepted by one and not the other.
+            let digest = <frame_system::Module<T>>::digest();
+            find_pre_block(digest.logs.iter().filter_map(|d| d.as_pre_runtime()))
+                .expect("Frontier pre-runtime digests are invalid");
+            // Reads: the digest above. Then the work of `on_finalize` besides the per
+            // transaction part counted by `transact`. Reads: `PendingCount`, `CurrentBlock` for
+            // the parent hash, the `frame_system` digest for the author and
+            // `pallet_timestamp::Now`. Writes: `PendingCount`, `CurrentBlock`, `CurrentReceipts`,
+            // `CurrentTransactionStatuses`, the digest and `BlockGasUsed`. Storage read by
+            // `T::FindAuthor` is not included.
+            T::DbWeight::get().reads_writes(5, 6)
+        }
+
+        fn on_runtime_upgrade() -> Weight {
+            if StorageVersion::get() >= STORAGE_VERSION {
+                return T::DbWeight::get().reads(1);
+            }
+            // Pending transactions used to be kept as a single `Vec` until the next block. Its
+            // contents were already stored in `CurrentBlock`, so it is dropped rather than moved.
+            migration::remove_storage_prefix(b"Ethereum", b"Pending", &[]);
+            StorageVersion::put(STORAGE_VERSION);
+            T::DbWeight::get().reads_writes(1, 2)
         }
     }
 }
 
+/// Version of the storage layout. Version 1 replaced the `Pending` vector with `PendingCount` and
+/// `PendingTransactions`.
+const STORAGE_VERSION: u32 = 1;
+
+/// Number of blocks a validated Ethereum transaction stays valid before it is revalidated.
+const TRANSACTION_LONGEVITY: u64 = 256;
+
+/// Half of the secp256k1 curve order, the largest canonical signature `s` value (EIP-2).
+const SECP256K1N_HALF: U256 = U256([
+    0xdfe92f46681b20a0,
+    0x5d576e7357a4501d,
+    0xffffffffffffffff,
+    0x7fffffffffffffff,
+]);
+
+/// Maximum size of contract creation code (EIP-3860).
+const MAX_INITCODE_SIZE: usize = 2 * 24576;
+
 #[repr(u8)]
-enum TransactionValidationError {
-    #[allow(dead_code)]
+pub enum TransactionValidationError {
     UnknownError,
     InvalidChainId,
     InvalidSignature,
+    GasLimitTooLow,
+    GasLimitTooHigh,
+    GasPriceTooLow,
+    BalanceTooLow,
+    MaxInitCodeSizeExceeded,
+    UnprotectedTransaction,
+    NonCanonicalSignature,
+}
+
+impl TransactionValidationError {
+    pub fn from_code(code: u8) -> Option<Self> {
+        use TransactionValidationError::*;
+        Some(match code {
+            0 => UnknownError,
+            1 => InvalidChainId,
+            2 => InvalidSignature,
+            3 => GasLimitTooLow,
+            4 => GasLimitTooHigh,
+            5 => GasPriceTooLow,
+            6 => BalanceTooLow,
+            7 => MaxInitCodeSizeExceeded,
+            8 => UnprotectedTransaction,
+            9 => NonCanonicalSignature,
+            _ => return None,
+        })
+    }
+
+    /// Error message geth returns for the same condition.
+    pub fn message(&self) -> &'static str {
+        use TransactionValidationError::*;
+        match self {
+            UnknownError => "unknown error",
+            InvalidChainId => "invalid chain id",
+            InvalidSignature => "invalid sender",
+            GasLimitTooLow => "intrinsic gas too low",
+            GasLimitTooHigh => "exceeds block gas limit",
+            GasPriceTooLow => "transaction underpriced",
+            BalanceTooLow => "insufficient funds for gas * price + value",
+            MaxInitCodeSizeExceeded => "max initcode size exceeded",
+            UnprotectedTransaction => "only replay-protected (EIP-155) transactions allowed",
+            NonCanonicalSignature => "invalid transaction v, r, s values",
+        }
+    }
 }
 
 impl<T: Config> frame_support::unsigned::ValidateUnsigned for Module<T> {
     type Call = Call<T>;
     fn validate_unsigned(_source: TransactionSource, call: &Self::Call) -> TransactionValidity {
         if let Call::transact(transaction) = call {
-            if let Some(chain_id) = transaction.signature.chain_id() {
-                if chain_id != T::ChainId::get() {
-                    return InvalidTransaction::Custom(
-                        TransactionValidationError::InvalidChainId as u8,
-                    )
-                        .into();
-                }
-            }
             let origin = Self::recover_signer(&transaction).ok_or_else(|| {
                 InvalidTransaction::Custom(TransactionValidationError::InvalidSignature as u8)
             })?;
+            Self::validate_signature_policy(transaction, &origin)
+                .map_err(|e| InvalidTransaction::Custom(e as u8))?;
             let account_data = pallet_evm::Module::<T>::account_basic(&origin);
             if transaction.nonce < account_data.nonce {
                 return InvalidTransaction::Stale.into();
             }
-            let fee = transaction.gas_price.saturating_mul(transaction.gas_limit);
-            if account_data.balance < fee {
-                return InvalidTransaction::Payment.into();
-            }
-            let mut builder =
-                ValidTransactionBuilder::default().and_provides((origin, transaction.nonce));
+            Self::validate_intrinsic(transaction, account_data.balance)
+                .map_err(|e| InvalidTransaction::Custom(e as u8))?;
+            // Higher paying transactions are included first and replace pooled transactions with
+            // the same sender and nonce. The RPC submit path additionally requires a minimum
+            // price bump for replacements.
+            let priority = transaction
+                .gas_price
+                .min(U256::from(u64::max_value()))
+                .low_u64();
+            let mut builder = ValidTransactionBuilder::default()
+                .and_provides((origin, transaction.nonce))
+                .priority(priority)
+                .longevity(TRANSACTION_LONGEVITY);
             if transaction.nonce > account_data.nonce {
                 if let Some(prev_nonce) = transaction.nonce.checked_sub(1.into()) {
                     builder = builder.and_requires((origin, prev_nonce))
@@ -218,9 +342,89 @@ impl<T: Config> frame_support::unsigned::ValidateUnsigned for Module<T> {
             Err(InvalidTransaction::Call.into())
         }
     }
+
+    fn pre_dispatch(call: &Self::Call) -> Result<(), TransactionValidityError> {
+        Self::validate_unsigned(TransactionSource::InBlock, call)?;
+        // `BlockGasUsed` is only set while a block is built, so the pool never sees it. Here it
+        // leaves transactions that no longer fit in the block being built in the pool.
+        if let Call::transact(transaction) = call {
+            if transaction.gas_limit > Self::remaining_block_gas() {
+                return Err(InvalidTransaction::ExhaustsResources.into());
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<T: Config> Module<T> {
+    /// Storage work of `transact` besides the EVM execution. Reads: `BlockGasUsed`,
+    /// `PendingCount` and, in `store_block`, the new `PendingTransactions` entry. Writes: that
+    /// entry when inserted and when taken, `PendingCount` and `BlockGasUsed`.
+    fn transact_storage_weight() -> Weight {
+        T::DbWeight::get().reads_writes(3, 4)
+    }
+
+    fn validate_intrinsic(
+        transaction: &ethereum::Transaction,
+        balance: U256,
+    ) -> Result<(), TransactionValidationError> {
+        let config = <T as pallet_evm::Config>::config();
+        let is_create = transaction.action == ethereum::TransactionAction::Create;
+        if is_create && transaction.input.len() > MAX_INITCODE_SIZE {
+            return Err(TransactionValidationError::MaxInitCodeSizeExceeded);
+        }
+        let zero_bytes = transaction.input.iter().filter(|b| **b == 0).count() as u64;
+        let non_zero_bytes = transaction.input.len() as u64 - zero_bytes;
+        let intrinsic_gas = if is_create {
+            config.gas_transaction_create
+        } else {
+            config.gas_transaction_call
+        }
+        .saturating_add(zero_bytes.saturating_mul(config.gas_transaction_zero_data))
+        .saturating_add(non_zero_bytes.saturating_mul(config.gas_transaction_non_zero_data));
+        if transaction.gas_limit < U256::from(intrinsic_gas) {
+            return Err(TransactionValidationError::GasLimitTooLow);
+        }
+        if transaction.gas_limit > T::BlockGasLimit::get() {
+            return Err(TransactionValidationError::GasLimitTooHigh);
+        }
+        if transaction.gas_price < T::FeeCalculator::min_gas_price() {
+            return Err(TransactionValidationError::GasPriceTooLow);
+        }
+        let fee = transaction.gas_price.saturating_mul(transaction.gas_limit);
+        if balance < fee.saturating_add(transaction.value) {
+            return Err(TransactionValidationError::BalanceTooLow);
+        }
+        Ok(())
+    }
+
+    fn validate_signature_policy(
+        transaction: &ethereum::Transaction,
+        origin: &H160,
+    ) -> Result<(), TransactionValidationError> {
+        match transaction.signature.chain_id() {
+            Some(chain_id) if chain_id != T::ChainId::get() => {
+                return Err(TransactionValidationError::InvalidChainId);
+            }
+            None if T::RequireReplayProtection::get()
+                && !T::UnprotectedSenders::get().contains(origin) =>
+            {
+                return Err(TransactionValidationError::UnprotectedTransaction);
+            }
+            _ => (),
+        }
+        if T::RequireLowS::get()
+            && U256::from_big_endian(transaction.signature.s().as_bytes()) > SECP256K1N_HALF
+        {
+            return Err(TransactionValidationError::NonCanonicalSignature);
+        }
+        Ok(())
+    }
+
+    fn remaining_block_gas() -> U256 {
+        T::BlockGasLimit::get().saturating_sub(BlockGasUsed::get())
+    }
+
     fn recover_signer(transaction: &ethereum::Transaction) -> Option<H160> {
         let mut sig = [0u8; 65];
         let mut msg = [0u8; 32];
@@ -240,17 +444,31 @@ impl<T: Config> Module<T> {
         let mut statuses = Vec::new();
         let mut receipts = Vec::new();
         let mut logs_bloom = Bloom::default();
-        for (transaction, status, receipt) in Pending::get() {
-            transactions.push(transaction);
-            statuses.push(status);
-            receipts.push(receipt.clone());
-            Self::logs_bloom(receipt.logs.clone(), &mut logs_bloom);
+        for index in 0..PendingCount::take() {
+            if let Some((transaction, status, receipt)) = PendingTransactions::take(index) {
+                transactions.push(transaction);
+                statuses.push(status);
+                receipts.push(receipt.clone());
+                Self::logs_bloom(receipt.logs.clone(), &mut logs_bloom);
+            }
         }
 
+        let (beneficiary, extra_data, mix_hash) = match Self::pre_block() {
+            Some(ConsensusLog::PreBlock {
+                beneficiary,
+                extra_data,
+                mix_hash,
+            }) => (beneficiary, extra_data, mix_hash),
+            _ => (
+                <Module<T>>::find_author().unwrap_or_default(),
+                Vec::new(),
+                H256::default(),
+            ),
+        };
         let ommers = Vec::<ethereum::Header>::new();
         let partial_header = ethereum::PartialHeader {
             parent_hash: Self::current_block_hash().unwrap_or_default(),
-            beneficiary: <Module<T>>::find_author(),
+            beneficiary,
             state_root: H256::default(),
             receipts_root: H256::from_slice(
                 Keccak256::digest(&rlp::encode_list(&receipts)[..]).as_slice(),
@@ -268,8 +486,8 @@ impl<T: Config> Module<T> {
             timestamp: UniqueSaturatedInto::<u64>::unique_saturated_into(
                 pallet_timestamp::Module::<T>::get(),
             ),
-            extra_data: Vec::new(),
-            mix_hash: H256::default(),
+            extra_data,
+            mix_hash,
             nonce: H64::default(),
         };
         let mut block = ethereum::Block::new(partial_header, transactions.clone(), ommers);
@@ -306,11 +524,24 @@ impl<T: Config> Module<T> {
         }
     }
 
-    pub fn find_author() -> H160 {
+    /// The `PreBlock` log the block author put in the pre-runtime digests, if any. Blocks whose
+    /// Frontier pre-runtime digests are invalid never get past `on_initialize`.
+    pub fn pre_block() -> Option<ConsensusLog> {
+        let digest = <frame_system::Module<T>>::digest();
+        find_pre_block(digest.logs.iter().filter_map(|d| d.as_pre_runtime()))
+            .ok()
+            .flatten()
+    }
+
+    /// Beneficiary of the current block: the author's `PreBlock` choice, else `FindAuthor`.
+    pub fn find_author() -> Option<H160> {
+        if let Some(ConsensusLog::PreBlock { beneficiary, .. }) = Self::pre_block() {
+            return Some(beneficiary);
+        }
         let digest = <frame_system::Module<T>>::digest();
         let pre_runtime_digests = digest.logs.iter().filter_map(|d| d.as_pre_runtime());
 
-        T::FindAuthor::find_author(pre_runtime_digests).unwrap_or_default()
+        T::FindAuthor::find_author(pre_runtime_digests)
     }
 
     pub fn current_transaction_statuses() -> Option<Vec<TransactionStatus>> {
@@ -339,6 +570,8 @@ impl<T: Config> Module<T> {
         action: TransactionAction,
         config: Option<evm::Config>,
     ) -> Result<(Option<H160>, Option<H160>, CallOrCreateInfo), DispatchError> {
+        // Saturate instead of truncating, so that a huge limit does not wrap around.
+        let gas_limit = gas_limit.min(U256::from(u64::max_value())).low_u64();
         match action {
             ethereum::TransactionAction::Call(target) => {
                 let res = T::Runner::call(
@@ -346,7 +579,7 @@ impl<T: Config> Module<T> {
                     target,
                     input.clone(),
                     value,
-                    gas_limit.low_u64(),
+                    gas_limit,
                     gas_price,
                     nonce,
                     config.as_ref().unwrap_or(T::config()),
@@ -358,7 +591,7 @@ impl<T: Config> Module<T> {
                     from,
                     input.clone(),
                     value,
-                    gas_limit.low_u64(),
+                    gas_limit,
                     gas_price,
                     nonce,
                     config.as_ref().unwrap_or(T::config()),
@@ -367,4 +600,45 @@ impl<T: Config> Module<T> {
             }
         }
     }
+
+    /// Execute the calls of every block in order on top of their overrides, carrying the state
+    /// from one call to the next. Nothing is persisted.
+    pub fn simulate(
+        blocks: Vec<SimulateBlock>,
+        trace_transfers: bool,
+    ) -> Vec<Vec<Result<CallOrCreateInfo, DispatchError>>> {
+        pallet_evm::Module::<T>::in_simulation(trace_transfers, || {
+            blocks
+                .into_iter()
+                .map(|block| {
+                    if let Err(e) = pallet_evm::Module::<T>::apply_overrides(
+                        &block.state_overrides,
+                        block.block_override,
+                    ) {
+                        return block.calls.iter().map(|_| Err(e)).collect();
+                    }
+                    block
+                        .calls
+                        .into_iter()
+                        .map(|call| {
+                            Self::execute(
+                                call.from,
+                                call.data,
+                                call.value,
+                                call.gas_limit,
+                                call.gas_price,
+                                call.nonce,
+                                match call.to {
+                                    Some(to) => TransactionAction::Call(to),
+                                    None => TransactionAction::Create,
+                                },
+                                None,
+                            )
+                                .map(|(_, _, info)| info)
+                        })
+                        .collect()
+                })
+                .collect()
+        })
+    }
 }
\ No newline at end of file
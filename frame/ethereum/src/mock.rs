@@ -0,0 +1,163 @@
+use crate as pallet_ethereum;
+use ethereum_types::{H160, H256, U256};
+use frame_support::{parameter_types, traits::Get};
+use pallet_evm::{
+    EVMCurrencyAdapter, EnsureAddressNever, EnsureAddressRoot, HashedAddressMapping,
+    SystemNonceStore,
+};
+use sp_runtime::{
+    testing::Header,
+    traits::{BlakeTwo256, IdentityLookup},
+    AccountId32,
+};
+use std::cell::RefCell;
+
+type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
+type Block = frame_system::mocking::MockBlock<Test>;
+
+frame_support::construct_runtime!(
+    pub enum Test where
+        Block = Block,
+        NodeBlock = Block,
+        UncheckedExtrinsic = UncheckedExtrinsic,
+    {
+        System: frame_system::{Module, Call, Config, Storage, Event<T>},
+        Balances: pallet_balances::{Module, Call, Storage, Config<T>, Event<T>},
+        Timestamp: pallet_timestamp::{Module, Call, Storage, Inherent},
+        EVM: pallet_evm::{Module, Call, Storage, Config, Event<T>},
+        Ethereum: pallet_ethereum::{Module, Call, Storage, Config, Event, ValidateUnsigned},
+    }
+);
+
+parameter_types! {
+    pub const BlockHashCount: u64 = 250;
+}
+
+impl frame_system::Config for Test {
+    type BaseCallFilter = ();
+    type BlockWeights = ();
+    type BlockLength = ();
+    type DbWeight = ();
+    type Origin = Origin;
+    type Index = u64;
+    type BlockNumber = u64;
+    type Call = Call;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = AccountId32;
+    type Lookup = IdentityLookup<Self::AccountId>;
+    type Header = Header;
+    type Event = Event;
+    type BlockHashCount = BlockHashCount;
+    type Version = ();
+    type PalletInfo = PalletInfo;
+    type AccountData = pallet_balances::AccountData<u64>;
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type SystemWeightInfo = ();
+    type SS58Prefix = ();
+}
+
+parameter_types! {
+    pub const ExistentialDeposit: u64 = 1;
+}
+
+impl pallet_balances::Config for Test {
+    type MaxLocks = ();
+    type Balance = u64;
+    type DustRemoval = ();
+    type Event = Event;
+    type ExistentialDeposit = ExistentialDeposit;
+    type AccountStore = System;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const MinimumPeriod: u64 = 1000;
+}
+
+impl pallet_timestamp::Config for Test {
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = MinimumPeriod;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const ChainId: u64 = 42;
+}
+
+impl pallet_evm::Config for Test {
+    type FeeCalculator = ();
+    type GasWeightMapping = ();
+    type CallOrigin = EnsureAddressRoot<AccountId32>;
+    type WithdrawOrigin = EnsureAddressNever<AccountId32>;
+    type AddressMapping = HashedAddressMapping<BlakeTwo256>;
+    type NonceStore = SystemNonceStore<Self>;
+    type Currency = Balances;
+    type BalanceConversion = ();
+    type OnChargeTransaction = EVMCurrencyAdapter<()>;
+    type Event = Event;
+    type Precompiles = ();
+    type ChainId = ChainId;
+    type Runner = pallet_evm::runner::stack::Runner<Self>;
+}
+
+parameter_types! {
+    pub BlockGasLimit: U256 = U256::from(15_000_000);
+}
+
+thread_local! {
+    static REQUIRE_REPLAY_PROTECTION: RefCell<bool> = RefCell::new(false);
+    static UNPROTECTED_SENDERS: RefCell<Vec<H160>> = RefCell::new(Vec::new());
+    static REQUIRE_LOW_S: RefCell<bool> = RefCell::new(false);
+}
+
+pub struct RequireReplayProtection;
+impl Get<bool> for RequireReplayProtection {
+    fn get() -> bool {
+        REQUIRE_REPLAY_PROTECTION.with(|v| *v.borrow())
+    }
+}
+
+pub struct UnprotectedSenders;
+impl Get<Vec<H160>> for UnprotectedSenders {
+    fn get() -> Vec<H160> {
+        UNPROTECTED_SENDERS.with(|v| v.borrow().clone())
+    }
+}
+
+pub struct RequireLowS;
+impl Get<bool> for RequireLowS {
+    fn get() -> bool {
+        REQUIRE_LOW_S.with(|v| *v.borrow())
+    }
+}
+
+/// Set the signature policy of the mock runtime for the current test thread.
+pub fn set_signature_policy(
+    require_replay_protection: bool,
+    unprotected_senders: Vec<H160>,
+    require_low_s: bool,
+) {
+    REQUIRE_REPLAY_PROTECTION.with(|v| *v.borrow_mut() = require_replay_protection);
+    UNPROTECTED_SENDERS.with(|v| *v.borrow_mut() = unprotected_senders);
+    REQUIRE_LOW_S.with(|v| *v.borrow_mut() = require_low_s);
+}
+
+impl pallet_ethereum::Config for Test {
+    type Event = Event;
+    type FindAuthor = ();
+    type StateRoot = pallet_ethereum::IntermediateStateRoot;
+    type BlockGasLimit = BlockGasLimit;
+    type RequireReplayProtection = RequireReplayProtection;
+    type UnprotectedSenders = UnprotectedSenders;
+    type RequireLowS = RequireLowS;
+}
+
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    frame_system::GenesisConfig::default()
+        .build_storage::<Test>()
+        .unwrap()
+        .into()
+}
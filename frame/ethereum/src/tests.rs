@@ -0,0 +1,308 @@
+use crate::{mock::*, BlockGasUsed, Call, TransactionValidationError};
+use ap_consensus::{ConsensusLog, FRONTIER_ENGINE_ID};
+use ap_evm::{BlockOverride, CallOrCreateInfo, SimulateBlock, SimulateCall, StateOverride};
+use codec::Encode;
+use ethereum_types::{H160, H256, U256};
+use frame_support::{
+    assert_noop, assert_ok,
+    traits::{Get, OnFinalize, OnInitialize},
+    unsigned::ValidateUnsigned,
+};
+use sha3::{Digest, Keccak256};
+use sp_runtime::{
+    generic::DigestItem,
+    transaction_validity::{InvalidTransaction, TransactionSource},
+};
+
+#[test]
+fn simulate_resets_block_override_between_blocks() {
+    new_test_ext().execute_with(|| {
+        let contract = H160::repeat_byte(0x11);
+        // COINBASE PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN
+        let code = vec![0x41, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3];
+        let coinbase = H160::repeat_byte(0x42);
+        let call = SimulateCall {
+            from: H160::repeat_byte(0x01),
+            to: Some(contract),
+            gas_limit: U256::from(100_000),
+            ..Default::default()
+        };
+
+        let results = Ethereum::simulate(
+            vec![
+                SimulateBlock {
+                    state_overrides: vec![(
+                        contract,
+                        StateOverride {
+                            code: Some(code),
+                            ..Default::default()
+                        },
+                    )],
+                    block_override: Some(BlockOverride {
+                        coinbase: Some(coinbase),
+                        ..Default::default()
+                    }),
+                    calls: vec![call.clone()],
+                },
+                SimulateBlock {
+                    state_overrides: Vec::new(),
+                    block_override: None,
+                    calls: vec![call],
+                },
+            ],
+            false,
+        );
+
+        let coinbases: Vec<H160> = results
+            .into_iter()
+            .map(|block| match &block[..] {
+                [Ok(CallOrCreateInfo::Call(info))] => H160::from(H256::from_slice(&info.value)),
+                _ => panic!("expected a single successful call per block"),
+            })
+            .collect();
+        assert_eq!(coinbases, vec![coinbase, H160::zero()]);
+    });
+}
+
+#[test]
+fn pending_migration_runs_once() {
+    use frame_support::{storage::migration, traits::OnRuntimeUpgrade};
+
+    new_test_ext().execute_with(|| {
+        migration::put_storage_value(b"Ethereum", b"Pending", &[], vec![0u8; 4]);
+        Ethereum::on_runtime_upgrade();
+        assert!(!migration::have_storage_value(b"Ethereum", b"Pending", &[]));
+
+        migration::put_storage_value(b"Ethereum", b"Pending", &[], vec![0u8; 4]);
+        Ethereum::on_runtime_upgrade();
+        assert!(migration::have_storage_value(b"Ethereum", b"Pending", &[]));
+    });
+}
+
+fn pre_block_log(beneficiary: H160) -> DigestItem<H256> {
+    DigestItem::PreRuntime(
+        FRONTIER_ENGINE_ID,
+        ConsensusLog::PreBlock {
+            beneficiary,
+            extra_data: Vec::new(),
+            mix_hash: H256::zero(),
+        }
+        .encode(),
+    )
+}
+
+#[test]
+fn pre_block_sets_author() {
+    new_test_ext().execute_with(|| {
+        let beneficiary = H160::repeat_byte(0x42);
+        System::deposit_log(pre_block_log(beneficiary));
+        Ethereum::on_initialize(1);
+        assert_eq!(Ethereum::find_author(), Some(beneficiary));
+    });
+}
+
+#[test]
+#[should_panic(expected = "Frontier pre-runtime digests are invalid")]
+fn duplicate_pre_block_logs_are_rejected() {
+    new_test_ext().execute_with(|| {
+        let beneficiary = H160::repeat_byte(0x42);
+        System::deposit_log(pre_block_log(beneficiary));
+        System::deposit_log(pre_block_log(beneficiary));
+        assert!(Ethereum::pre_block().is_none());
+        assert_eq!(Ethereum::find_author(), None);
+        Ethereum::on_initialize(1);
+    });
+}
+
+fn transaction(action: ethereum::TransactionAction, input: Vec<u8>) -> ethereum::Transaction {
+    ethereum::Transaction {
+        nonce: U256::zero(),
+        gas_price: U256::zero(),
+        gas_limit: U256::from(100_000),
+        action,
+        value: U256::zero(),
+        input,
+        signature: ethereum::TransactionSignature::new(
+            27,
+            H256::repeat_byte(0x01),
+            H256::repeat_byte(0x01),
+        )
+        .unwrap(),
+    }
+}
+
+fn validate_intrinsic(transaction: &ethereum::Transaction, balance: u64) -> Result<(), u8> {
+    Ethereum::validate_intrinsic(transaction, U256::from(balance)).map_err(|e| e as u8)
+}
+
+#[test]
+fn intrinsic_gas_counts_zero_and_non_zero_bytes() {
+    new_test_ext().execute_with(|| {
+        let too_low = Err(TransactionValidationError::GasLimitTooLow as u8);
+
+        // 21000 + 4 per zero byte + 16 per non-zero byte.
+        let mut call = transaction(ethereum::TransactionAction::Call(H160::zero()), vec![0, 1, 1]);
+        call.gas_limit = U256::from(21_035);
+        assert_eq!(validate_intrinsic(&call, 0), too_low);
+        call.gas_limit = U256::from(21_036);
+        assert_eq!(validate_intrinsic(&call, 0), Ok(()));
+
+        let mut create = transaction(ethereum::TransactionAction::Create, Vec::new());
+        create.gas_limit = U256::from(52_999);
+        assert_eq!(validate_intrinsic(&create, 0), too_low);
+        create.gas_limit = U256::from(53_000);
+        assert_eq!(validate_intrinsic(&create, 0), Ok(()));
+    });
+}
+
+#[test]
+fn intrinsic_checks_reject_invalid_transactions() {
+    new_test_ext().execute_with(|| {
+        let create = transaction(ethereum::TransactionAction::Create, vec![1; 2 * 24576 + 1]);
+        assert_eq!(
+            validate_intrinsic(&create, 0),
+            Err(TransactionValidationError::MaxInitCodeSizeExceeded as u8)
+        );
+
+        let mut call = transaction(ethereum::TransactionAction::Call(H160::zero()), Vec::new());
+        call.gas_limit = BlockGasLimit::get() + 1;
+        assert_eq!(
+            validate_intrinsic(&call, 0),
+            Err(TransactionValidationError::GasLimitTooHigh as u8)
+        );
+
+        call.gas_limit = U256::from(21_000);
+        call.gas_price = U256::one();
+        call.value = U256::one();
+        assert_eq!(
+            validate_intrinsic(&call, 21_000),
+            Err(TransactionValidationError::BalanceTooLow as u8)
+        );
+        assert_eq!(validate_intrinsic(&call, 21_001), Ok(()));
+    });
+}
+
+fn signer() -> (secp256k1::SecretKey, H160) {
+    let secret = secp256k1::SecretKey::parse(&[0x01; 32]).unwrap();
+    let public = secp256k1::PublicKey::from_secret_key(&secret);
+    let address = H160::from(H256::from_slice(
+        Keccak256::digest(&public.serialize()[1..65]).as_slice(),
+    ));
+    (secret, address)
+}
+
+/// A value-less call signed by `signer()` for the mock chain.
+fn signed_transaction(nonce: u64, gas_limit: u64) -> ethereum::Transaction {
+    let message = ethereum::TransactionMessage {
+        nonce: U256::from(nonce),
+        gas_price: U256::zero(),
+        gas_limit: U256::from(gas_limit),
+        action: ethereum::TransactionAction::Call(H160::repeat_byte(0x02)),
+        value: U256::zero(),
+        input: Vec::new(),
+        chain_id: Some(ChainId::get()),
+    };
+    let (secret, _) = signer();
+    let signing_message = secp256k1::Message::parse_slice(&message.hash()[..]).unwrap();
+    let (signature, recid) = secp256k1::sign(&signing_message, &secret);
+    let rs = signature.serialize();
+    ethereum::Transaction {
+        nonce: message.nonce,
+        gas_price: message.gas_price,
+        gas_limit: message.gas_limit,
+        action: message.action,
+        value: message.value,
+        input: message.input,
+        signature: ethereum::TransactionSignature::new(
+            2 * ChainId::get() + 35 + recid.serialize() as u64,
+            H256::from_slice(&rs[0..32]),
+            H256::from_slice(&rs[32..64]),
+        )
+        .unwrap(),
+    }
+}
+
+#[test]
+fn pre_dispatch_checks_remaining_block_gas() {
+    new_test_ext().execute_with(|| {
+        let call = Call::<Test>::transact(signed_transaction(0, 21_000));
+
+        BlockGasUsed::put(BlockGasLimit::get() - 21_000);
+        assert_ok!(Ethereum::pre_dispatch(&call));
+
+        BlockGasUsed::put(BlockGasLimit::get() - 20_999);
+        assert_eq!(
+            Ethereum::pre_dispatch(&call),
+            Err(InvalidTransaction::ExhaustsResources.into())
+        );
+        // The pool does not know about the block being built.
+        assert!(Ethereum::validate_unsigned(TransactionSource::External, &call).is_ok());
+    });
+}
+
+#[test]
+fn block_gas_used_accumulates_and_resets_on_finalize() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(Ethereum::transact(Origin::none(), signed_transaction(0, 21_000)));
+        assert_eq!(BlockGasUsed::get(), U256::from(21_000));
+        assert_ok!(Ethereum::transact(Origin::none(), signed_transaction(1, 50_000)));
+        assert_eq!(BlockGasUsed::get(), U256::from(42_000));
+
+        BlockGasUsed::put(BlockGasLimit::get() - 20_999);
+        assert_noop!(
+            Ethereum::transact(Origin::none(), signed_transaction(2, 21_000)),
+            crate::Error::<Test>::BlockGasLimitExceeded
+        );
+
+        Ethereum::on_finalize(1);
+        assert_eq!(BlockGasUsed::get(), U256::zero());
+    });
+}
+
+fn signature_policy(transaction: &ethereum::Transaction, origin: H160) -> Result<(), u8> {
+    Ethereum::validate_signature_policy(transaction, &origin).map_err(|e| e as u8)
+}
+
+#[test]
+fn replay_protection_rejects_unprotected_transactions() {
+    new_test_ext().execute_with(|| {
+        let unprotected = Err(TransactionValidationError::UnprotectedTransaction as u8);
+        let sender = H160::repeat_byte(0x01);
+        // v = 27 carries no chain id.
+        let legacy = transaction(ethereum::TransactionAction::Call(H160::zero()), Vec::new());
+        let (_, signer) = signer();
+        let protected = signed_transaction(0, 21_000);
+        assert_eq!(signature_policy(&legacy, sender), Ok(()));
+
+        set_signature_policy(true, Vec::new(), false);
+        assert_eq!(signature_policy(&legacy, sender), unprotected);
+        assert_eq!(signature_policy(&protected, signer), Ok(()));
+
+        set_signature_policy(true, vec![sender], false);
+        assert_eq!(signature_policy(&legacy, sender), Ok(()));
+        assert_eq!(signature_policy(&legacy, H160::repeat_byte(0x02)), unprotected);
+    });
+}
+
+#[test]
+fn low_s_policy_rejects_high_s_signatures() {
+    new_test_ext().execute_with(|| {
+        let sender = H160::repeat_byte(0x01);
+        let low_s = transaction(ethereum::TransactionAction::Call(H160::zero()), Vec::new());
+        let mut high_s = low_s.clone();
+        high_s.signature = ethereum::TransactionSignature::new(
+            27,
+            H256::repeat_byte(0x01),
+            H256::repeat_byte(0x80),
+        )
+        .unwrap();
+        assert_eq!(signature_policy(&high_s, sender), Ok(()));
+
+        set_signature_policy(false, Vec::new(), true);
+        assert_eq!(
+            signature_policy(&high_s, sender),
+            Err(TransactionValidationError::NonCanonicalSignature as u8)
+        );
+        assert_eq!(signature_policy(&low_s, sender), Ok(()));
+    });
+}
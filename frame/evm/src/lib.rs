@@ -1,19 +1,25 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
 pub mod runner;
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
 
 pub use crate::runner::Runner;
 pub use evm::{ExitError, ExitFatal, ExitReason, ExitRevert, ExitSucceed};
 pub use ap_evm::{
-    Account, CallInfo, CreateInfo, ExecutionInfo, LinearCostPrecompile, Log, Precompile,
-    PrecompileSet, Vicinity,
+    Account, BlockOverride, CallInfo, CreateInfo, ExecutionInfo, LinearCostPrecompile, Log,
+    Precompile, PrecompileSet, StateOverride, Vicinity,
 };
 
 #[cfg(feature = "std")]
 use codec::{Decode, Encode};
 use evm::Config as EvmConfig;
 use frame_support::dispatch::DispatchResultWithPostInfo;
-use frame_support::traits::{Currency, ExistenceRequirement, Get, WithdrawReasons};
+use frame_support::traits::{
+    Currency, ExistenceRequirement, FindAuthor, Get, Imbalance, OnUnbalanced, WithdrawReasons,
+};
 use frame_support::weights::{Pays, PostDispatchInfo, Weight};
 use frame_support::{decl_error, decl_event, decl_module, decl_storage};
 use frame_system::RawOrigin;
@@ -21,14 +27,18 @@ use frame_system::RawOrigin;
 use serde::{Deserialize, Serialize};
 use sp_core::{Hasher, H160, H256, U256};
 use sp_runtime::{
-    traits::{BadOrigin, UniqueSaturatedInto},
-    AccountId32,
+    traits::{AtLeast32BitUnsigned, BadOrigin, Bounded, Saturating, UniqueSaturatedInto},
+    AccountId32, DispatchError, TransactionOutcome,
 };
-use sp_std::vec::Vec;
+use sp_std::{convert::TryInto, vec::Vec};
 
 pub type BalanceOf<T> =
 <<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
 
+pub type NegativeImbalanceOf<T> = <<T as Config>::Currency as Currency<
+    <T as frame_system::Config>::AccountId,
+>>::NegativeImbalance;
+
 pub trait FeeCalculator {
     fn min_gas_price() -> U256;
 }
@@ -155,6 +165,233 @@ impl GasWeightMapping for () {
     }
 }
 
+/// Converts between EVM balances in wei and native balances, which may have fewer decimals.
+pub trait BalanceConversion<Balance: AtLeast32BitUnsigned> {
+    /// Wei in one smallest native unit.
+    fn wei_per_unit() -> U256;
+
+    /// Wei of `balance`. `None` if it does not fit in a `U256`.
+    fn to_evm(balance: Balance) -> Option<U256> {
+        let balance: u128 = balance.try_into().ok()?;
+        U256::from(balance).checked_mul(Self::wei_per_unit())
+    }
+
+    /// Native units of exactly `value` wei. `None` if `value` is not a whole number of native
+    /// units or does not fit in `Balance`.
+    fn to_native(value: U256) -> Option<Balance> {
+        let (units, remainder) = value.div_mod(Self::wei_per_unit());
+        if !remainder.is_zero() {
+            return None;
+        }
+        u256_to_balance(units)
+    }
+
+    /// Native units of `value` wei rounded down, for amounts credited to an account.
+    fn to_native_floor(value: U256) -> Option<Balance> {
+        u256_to_balance(value / Self::wei_per_unit())
+    }
+
+    /// Native units of `value` wei rounded up, for amounts debited from an account.
+    fn to_native_ceil(value: U256) -> Option<Balance> {
+        let (units, remainder) = value.div_mod(Self::wei_per_unit());
+        if remainder.is_zero() {
+            u256_to_balance(units)
+        } else {
+            u256_to_balance(units.checked_add(U256::one())?)
+        }
+    }
+}
+
+fn u256_to_balance<Balance: AtLeast32BitUnsigned>(value: U256) -> Option<Balance> {
+    if value > U256::from(u128::max_value()) {
+        return None;
+    }
+    value.low_u128().try_into().ok()
+}
+
+/// Native balances with 18 decimals, the same as wei.
+impl<Balance: AtLeast32BitUnsigned> BalanceConversion<Balance> for () {
+    fn wei_per_unit() -> U256 {
+        U256::one()
+    }
+}
+
+/// Native balances with `Decimals` decimals, at most 18. More decimals than wei are a
+/// configuration error that the pallet's `integrity_test` reports.
+pub struct NativeDecimals<Decimals>(sp_std::marker::PhantomData<Decimals>);
+
+impl<Balance: AtLeast32BitUnsigned, Decimals: Get<u8>> BalanceConversion<Balance>
+    for NativeDecimals<Decimals>
+{
+    fn wei_per_unit() -> U256 {
+        let decimals = Decimals::get();
+        assert!(decimals <= 18, "NativeDecimals supports at most 18 decimals");
+        U256::exp10((18 - decimals) as usize)
+    }
+}
+
+/// Charges the gas fee of an EVM execution.
+pub trait OnChargeEVMTransaction<T: Config> {
+    /// What `withdraw_fee` took, handed back to `correct_and_deposit_fee`.
+    type LiquidityInfo: Default;
+
+    /// Withdraw the maximum fee `fee`, in wei, before execution.
+    fn withdraw_fee(who: &H160, fee: U256) -> Result<Self::LiquidityInfo, Error<T>>;
+
+    /// Refund what exceeds the `corrected_fee` actually used and handle the rest.
+    fn correct_and_deposit_fee(
+        who: &H160,
+        corrected_fee: U256,
+        already_withdrawn: Self::LiquidityInfo,
+    );
+}
+
+/// Charges fees in `Config::Currency` and hands the fee actually paid to `OU`. With `()` the
+/// fee is burned.
+pub struct EVMCurrencyAdapter<OU>(sp_std::marker::PhantomData<OU>);
+
+impl<T, OU> OnChargeEVMTransaction<T> for EVMCurrencyAdapter<OU>
+    where
+        T: Config,
+        OU: OnUnbalanced<NegativeImbalanceOf<T>>,
+{
+    type LiquidityInfo = Option<NegativeImbalanceOf<T>>;
+
+    fn withdraw_fee(who: &H160, fee: U256) -> Result<Self::LiquidityInfo, Error<T>> {
+        if fee.is_zero() {
+            return Ok(None);
+        }
+        let account_id = T::AddressMapping::into_account_id(*who);
+        let fee = T::BalanceConversion::to_native_ceil(fee).ok_or(Error::<T>::FeeOverflow)?;
+        let imbalance = T::Currency::withdraw(
+            &account_id,
+            fee,
+            WithdrawReasons::FEE,
+            ExistenceRequirement::AllowDeath,
+        )
+            .map_err(|_| Error::<T>::BalanceLow)?;
+        Ok(Some(imbalance))
+    }
+
+    fn correct_and_deposit_fee(
+        who: &H160,
+        corrected_fee: U256,
+        already_withdrawn: Self::LiquidityInfo,
+    ) {
+        if let Some(paid) = already_withdrawn {
+            let account_id = T::AddressMapping::into_account_id(*who);
+            let corrected_fee = T::BalanceConversion::to_native_ceil(corrected_fee)
+                .unwrap_or_else(Bounded::max_value);
+            let refund = paid.peek().saturating_sub(corrected_fee);
+            let refund_imbalance = T::Currency::deposit_creating(&account_id, refund);
+            // The refund never exceeds what was paid.
+            let fee = paid
+                .offset(refund_imbalance)
+                .unwrap_or_else(|_| NegativeImbalanceOf::<T>::zero());
+            OU::on_unbalanced(fee);
+        }
+    }
+}
+
+/// Pays `AuthorShare` percent of the fees to the block author found by `Author` and the rest to
+/// `Treasury`. Without an author everything goes to `Treasury`; with `()` as `Treasury` that
+/// part is burned.
+pub struct AuthorAndTreasury<T, Author, Treasury, AuthorShare>(
+    sp_std::marker::PhantomData<(T, Author, Treasury, AuthorShare)>,
+);
+
+impl<T, Author, Treasury, AuthorShare> OnUnbalanced<NegativeImbalanceOf<T>>
+    for AuthorAndTreasury<T, Author, Treasury, AuthorShare>
+    where
+        T: Config,
+        Author: FindAuthor<H160>,
+        Treasury: OnUnbalanced<NegativeImbalanceOf<T>>,
+        AuthorShare: Get<u32>,
+{
+    fn on_nonzero_unbalanced(fee: NegativeImbalanceOf<T>) {
+        let digest = frame_system::Module::<T>::digest();
+        let author = Author::find_author(digest.logs.iter().filter_map(|d| d.as_pre_runtime()));
+        let (to_author, to_treasury) = match author {
+            Some(_) => {
+                let share = AuthorShare::get().min(100);
+                fee.ration(share, 100 - share)
+            }
+            None => (NegativeImbalanceOf::<T>::zero(), fee),
+        };
+        let author_amount = to_author.peek();
+        let treasury_amount = to_treasury.peek();
+        if let Some(author) = author {
+            T::Currency::resolve_creating(&T::AddressMapping::into_account_id(author), to_author);
+        }
+        Treasury::on_unbalanced(to_treasury);
+        Module::<T>::deposit_event(Event::<T>::FeeDistributed(
+            author,
+            author_amount,
+            treasury_amount,
+        ));
+    }
+}
+
+/// Where the nonces of EVM accounts are kept.
+pub trait NonceStore {
+    fn nonce(address: &H160) -> U256;
+    fn inc_nonce(address: &H160);
+    /// Fails without changing anything when `nonce` does not fit in the store.
+    fn set_nonce(address: &H160, nonce: U256) -> Result<(), DispatchError>;
+}
+
+/// Nonces kept in `frame_system` under the mapped account, shared with Substrate extrinsics.
+pub struct SystemNonceStore<T>(sp_std::marker::PhantomData<T>);
+
+impl<T: Config> NonceStore for SystemNonceStore<T> {
+    fn nonce(address: &H160) -> U256 {
+        let account_id = T::AddressMapping::into_account_id(*address);
+        let nonce = frame_system::Module::<T>::account_nonce(&account_id);
+        U256::from(UniqueSaturatedInto::<u128>::unique_saturated_into(nonce))
+    }
+
+    fn inc_nonce(address: &H160) {
+        let account_id = T::AddressMapping::into_account_id(*address);
+        frame_system::Module::<T>::inc_account_nonce(&account_id);
+    }
+
+    fn set_nonce(address: &H160, nonce: U256) -> Result<(), DispatchError> {
+        if nonce > U256::from(u128::max_value()) {
+            return Err(Error::<T>::NonceOverflow.into());
+        }
+        let nonce: T::Index = nonce
+            .low_u128()
+            .try_into()
+            .map_err(|_| Error::<T>::NonceOverflow)?;
+        let account_id = T::AddressMapping::into_account_id(*address);
+        frame_system::Account::<T>::mutate(&account_id, |info| info.nonce = nonce);
+        Ok(())
+    }
+}
+
+/// Nonces kept in this pallet, so Substrate extrinsics of a mapped account leave queued
+/// Ethereum transactions valid.
+pub struct EvmNonceStore<T>(sp_std::marker::PhantomData<T>);
+
+impl<T: Config> NonceStore for EvmNonceStore<T> {
+    fn nonce(address: &H160) -> U256 {
+        AccountNonces::get(address)
+    }
+
+    fn inc_nonce(address: &H160) {
+        AccountNonces::mutate(address, |nonce| *nonce = nonce.saturating_add(U256::one()));
+    }
+
+    fn set_nonce(address: &H160, nonce: U256) -> Result<(), DispatchError> {
+        if nonce.is_zero() {
+            AccountNonces::remove(address);
+        } else {
+            AccountNonces::insert(address, nonce);
+        }
+        Ok(())
+    }
+}
+
 static ISTANBUL_CONFIG: EvmConfig = EvmConfig::istanbul();
 
 pub trait Config: frame_system::Config + pallet_timestamp::Config {
@@ -168,8 +405,14 @@ pub trait Config: frame_system::Config + pallet_timestamp::Config {
 
     type AddressMapping: AddressMapping<Self::AccountId>;
 
+    type NonceStore: NonceStore;
+
     type Currency: Currency<Self::AccountId>;
 
+    type BalanceConversion: BalanceConversion<BalanceOf<Self>>;
+
+    type OnChargeTransaction: OnChargeEVMTransaction<Self>;
+
     type Event: From<Event<Self>> + Into<<Self as frame_system::Config>::Event>;
 
     type Precompiles: PrecompileSet;
@@ -192,11 +435,27 @@ pub struct GenesisAccount {
     pub code: Vec<u8>,
 }
 
+/// Block override and transfer tracing of the simulation in progress, if any.
+pub(crate) struct SimulationContext {
+    pub block_override: Option<BlockOverride>,
+    pub trace_transfers: bool,
+}
+
+environmental::environmental!(simulation: SimulationContext);
+
+/// Read the context of the simulation in progress. Outside of `Module::in_simulation` this is
+/// `None` without touching storage.
+pub(crate) fn simulation_context<R>(f: impl FnOnce(&SimulationContext) -> Option<R>) -> Option<R> {
+    simulation::with(|context| f(context)).flatten()
+}
+
 decl_storage! {
     trait Store for Module<T: Config> as EVM {
         AccountCodes get(fn account_codes): map hasher(blake2_128_concat) H160 => Vec<u8>;
         AccountStorages get(fn account_storages):
             double_map hasher(blake2_128_concat) H160, hasher(blake2_128_concat) H256 => H256;
+        /// Only used with `EvmNonceStore`.
+        AccountNonces get(fn account_nonces): map hasher(blake2_128_concat) H160 => U256;
     }
 
     add_extra_genesis {
@@ -204,14 +463,11 @@ decl_storage! {
         build(|config: &GenesisConfig| {
             for (address, account) in &config.accounts {
                 let account_id = T::AddressMapping::into_account_id(*address);
-                // `u128::max_value()`.
-                for _ in 0..account.nonce.low_u128() {
-                    frame_system::Module::<T>::inc_account_nonce(&account_id);
-                }
-                T::Currency::deposit_creating(
-                    &account_id,
-                    account.balance.low_u128().unique_saturated_into(),
-                );
+                T::NonceStore::set_nonce(address, account.nonce)
+                    .expect("Genesis nonce must fit in the account nonce");
+                let balance = T::BalanceConversion::to_native(account.balance)
+                    .expect("Genesis balance must be a whole native amount that fits in Balance");
+                T::Currency::deposit_creating(&account_id, balance);
                 AccountCodes::insert(address, &account.code);
                 for (index, value) in &account.storage {
                     AccountStorages::insert(address, index, value);
@@ -224,6 +480,7 @@ decl_storage! {
 decl_event! {
     pub enum Event<T> where
         <T as frame_system::Config>::AccountId,
+        Balance = BalanceOf<T>,
     {
         Log(Log),
         Created(H160),
@@ -232,6 +489,8 @@ decl_event! {
         ExecutedFailed(H160),
         BalanceDeposit(AccountId, H160, U256),
         BalanceWithdraw(AccountId, H160, U256),
+        /// EVM fees paid to the block author, if one was found, and to the treasury.
+        FeeDistributed(Option<H160>, Balance, Balance),
     }
 }
 
@@ -243,6 +502,7 @@ decl_error! {
         WithdrawFailed,
         GasPriceTooLow,
         InvalidNonce,
+        NonceOverflow,
     }
 }
 
@@ -251,6 +511,11 @@ decl_module! {
         type Error = Error<T>;
         fn deposit_event() = default;
 
+        fn integrity_test() {
+            // Panics on an invalid `BalanceConversion`, e.g. `NativeDecimals` above 18.
+            T::BalanceConversion::wei_per_unit();
+        }
+
         #[weight = 0]
         fn withdraw(origin, address: H160, value: BalanceOf<T>) {
             let destination = T::WithdrawOrigin::ensure_address_origin(&address, origin)?;
@@ -403,24 +668,109 @@ impl<T: Config> Module<T> {
     pub fn remove_account(address: &H160) {
         AccountCodes::remove(address);
         AccountStorages::remove_prefix(address);
+        AccountNonces::remove(address);
+    }
+
+    /// Run `f` on top of the given state and block overrides. Every storage change, including
+    /// the ones made by `f`, is rolled back before returning.
+    pub fn with_overrides<R>(
+        state_overrides: Vec<(H160, StateOverride)>,
+        block_override: Option<BlockOverride>,
+        f: impl FnOnce() -> R,
+    ) -> Result<R, DispatchError> {
+        Self::in_simulation(false, || {
+            Self::apply_overrides(&state_overrides, block_override)?;
+            Ok(f())
+        })
+    }
+
+    /// Run `f` in a storage transaction that is always rolled back. With `trace_transfers` set,
+    /// every non-zero value transfer made by the EVM is reported as an extra log.
+    pub fn in_simulation<R>(trace_transfers: bool, f: impl FnOnce() -> R) -> R {
+        let mut context = SimulationContext {
+            block_override: None,
+            trace_transfers,
+        };
+        frame_support::storage::with_transaction(|| {
+            TransactionOutcome::Rollback(simulation::using(&mut context, f))
+        })
+    }
+
+    /// Only meant to be called from within `in_simulation`. Replaces the block override of a
+    /// previous call, so a block without one sees the real block environment again.
+    pub fn apply_overrides(
+        state_overrides: &[(H160, StateOverride)],
+        block_override: Option<BlockOverride>,
+    ) -> Result<(), DispatchError> {
+        for (address, state_override) in state_overrides {
+            Self::apply_state_override(address, state_override)?;
+        }
+        simulation::with(|context| context.block_override = block_override);
+        Ok(())
+    }
+
+    fn apply_state_override(
+        address: &H160,
+        state_override: &StateOverride,
+    ) -> Result<(), DispatchError> {
+        let account_id = T::AddressMapping::into_account_id(*address);
+        if let Some(balance) = state_override.balance {
+            let balance = T::BalanceConversion::to_native_floor(balance)
+                .unwrap_or_else(Bounded::max_value);
+            T::Currency::make_free_balance_be(&account_id, balance);
+        }
+        if let Some(nonce) = state_override.nonce {
+            T::NonceStore::set_nonce(address, nonce)?;
+        }
+        if let Some(code) = &state_override.code {
+            AccountCodes::insert(address, code);
+        }
+        if let Some(state) = &state_override.state {
+            AccountStorages::remove_prefix(address);
+            for (index, value) in state {
+                AccountStorages::insert(address, index, value);
+            }
+        }
+        if let Some(state_diff) = &state_override.state_diff {
+            for (index, value) in state_diff {
+                AccountStorages::insert(address, index, value);
+            }
+        }
+        Ok(())
     }
 
     pub fn account_basic(address: &H160) -> Account {
         let account_id = T::AddressMapping::into_account_id(*address);
-        let nonce = frame_system::Module::<T>::account_nonce(&account_id);
         let balance = T::Currency::free_balance(&account_id);
         Account {
-            nonce: U256::from(UniqueSaturatedInto::<u128>::unique_saturated_into(nonce)),
-            balance: U256::from(UniqueSaturatedInto::<u128>::unique_saturated_into(balance)),
+            nonce: T::NonceStore::nonce(address),
+            balance: T::BalanceConversion::to_evm(balance).unwrap_or_else(U256::max_value),
+        }
+    }
+
+    /// Copy the `frame_system` nonces of `addresses` into `AccountNonces`, for runtimes
+    /// switching to `EvmNonceStore`. Returns the weight used.
+    ///
+    /// This is a helper for the runtime's own `on_runtime_upgrade`, not a complete migration:
+    /// `AddressMapping` cannot be inverted, so `frame_system::Account` cannot be walked for the
+    /// EVM addresses and the runtime has to list them. Any address left out restarts from nonce
+    /// zero, which allows its earlier transactions to be replayed.
+    pub fn copy_system_nonces(addresses: &[H160]) -> Weight {
+        for address in addresses {
+            // `AccountNonces` holds any U256, so this cannot fail.
+            let _ = EvmNonceStore::<T>::set_nonce(address, SystemNonceStore::<T>::nonce(address));
         }
+        T::DbWeight::get().reads_writes(addresses.len() as Weight, addresses.len() as Weight)
     }
 
+    #[deprecated(note = "fees are charged through `Config::OnChargeTransaction`")]
     pub fn withdraw_fee(address: &H160, value: U256) -> Result<(), Error<T>> {
         let account_id = T::AddressMapping::into_account_id(*address);
+        let value = T::BalanceConversion::to_native_ceil(value).ok_or(Error::<T>::FeeOverflow)?;
         drop(
             T::Currency::withdraw(
                 &account_id,
-                value.low_u128().unique_saturated_into(),
+                value,
                 WithdrawReasons::FEE,
                 ExistenceRequirement::AllowDeath,
             )
@@ -430,11 +780,11 @@ impl<T: Config> Module<T> {
         Ok(())
     }
 
+    #[deprecated(note = "fees are charged through `Config::OnChargeTransaction`")]
     pub fn deposit_fee(address: &H160, value: U256) {
         let account_id = T::AddressMapping::into_account_id(*address);
-        drop(T::Currency::deposit_creating(
-            &account_id,
-            value.low_u128().unique_saturated_into(),
-        ));
+        // Refunds never exceed the fee withdrawn before, so they always fit.
+        let value = T::BalanceConversion::to_native_floor(value).unwrap_or_default();
+        drop(T::Currency::deposit_creating(&account_id, value));
     }
 }
\ No newline at end of file
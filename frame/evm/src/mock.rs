@@ -0,0 +1,146 @@
+use crate::{
+    self as pallet_evm, AddressMapping, AuthorAndTreasury, EVMCurrencyAdapter, EnsureAddressNever,
+    EnsureAddressRoot, HashedAddressMapping, NegativeImbalanceOf, SystemNonceStore,
+};
+use frame_support::{
+    parameter_types,
+    traits::{Currency, FindAuthor, Get, OnUnbalanced},
+};
+use std::cell::RefCell;
+use sp_core::{H160, H256};
+use sp_runtime::{
+    testing::Header,
+    traits::{BlakeTwo256, IdentityLookup},
+    AccountId32, ConsensusEngineId,
+};
+
+type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
+type Block = frame_system::mocking::MockBlock<Test>;
+
+frame_support::construct_runtime!(
+    pub enum Test where
+        Block = Block,
+        NodeBlock = Block,
+        UncheckedExtrinsic = UncheckedExtrinsic,
+    {
+        System: frame_system::{Module, Call, Config, Storage, Event<T>},
+        Balances: pallet_balances::{Module, Call, Storage, Config<T>, Event<T>},
+        Timestamp: pallet_timestamp::{Module, Call, Storage, Inherent},
+        EVM: pallet_evm::{Module, Call, Storage, Config, Event<T>},
+    }
+);
+
+parameter_types! {
+    pub const BlockHashCount: u64 = 250;
+}
+
+impl frame_system::Config for Test {
+    type BaseCallFilter = ();
+    type BlockWeights = ();
+    type BlockLength = ();
+    type DbWeight = ();
+    type Origin = Origin;
+    type Index = u64;
+    type BlockNumber = u64;
+    type Call = Call;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = AccountId32;
+    type Lookup = IdentityLookup<Self::AccountId>;
+    type Header = Header;
+    type Event = Event;
+    type BlockHashCount = BlockHashCount;
+    type Version = ();
+    type PalletInfo = PalletInfo;
+    type AccountData = pallet_balances::AccountData<u64>;
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type SystemWeightInfo = ();
+    type SS58Prefix = ();
+}
+
+parameter_types! {
+    pub const ExistentialDeposit: u64 = 1;
+}
+
+impl pallet_balances::Config for Test {
+    type MaxLocks = ();
+    type Balance = u64;
+    type DustRemoval = ();
+    type Event = Event;
+    type ExistentialDeposit = ExistentialDeposit;
+    type AccountStore = System;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const MinimumPeriod: u64 = 1000;
+}
+
+impl pallet_timestamp::Config for Test {
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = MinimumPeriod;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const ChainId: u64 = 42;
+    pub const AuthorShare: u32 = 20;
+    pub TreasuryAccount: AccountId32 = AccountId32::new([0xff; 32]);
+}
+
+thread_local! {
+    static AUTHOR: RefCell<Option<H160>> = RefCell::new(None);
+}
+
+/// Set the block author of the mock runtime for the current test thread.
+pub fn set_author(author: Option<H160>) {
+    AUTHOR.with(|v| *v.borrow_mut() = author);
+}
+
+pub struct MockAuthor;
+impl FindAuthor<H160> for MockAuthor {
+    fn find_author<'a, I>(_digests: I) -> Option<H160>
+        where
+            I: 'a + IntoIterator<Item = (ConsensusEngineId, &'a [u8])>,
+    {
+        AUTHOR.with(|v| *v.borrow())
+    }
+}
+
+pub struct Treasury;
+impl OnUnbalanced<NegativeImbalanceOf<Test>> for Treasury {
+    fn on_nonzero_unbalanced(amount: NegativeImbalanceOf<Test>) {
+        Balances::resolve_creating(&TreasuryAccount::get(), amount);
+    }
+}
+
+impl pallet_evm::Config for Test {
+    type FeeCalculator = ();
+    type GasWeightMapping = ();
+    type CallOrigin = EnsureAddressRoot<AccountId32>;
+    type WithdrawOrigin = EnsureAddressNever<AccountId32>;
+    type AddressMapping = HashedAddressMapping<BlakeTwo256>;
+    type NonceStore = SystemNonceStore<Self>;
+    type Currency = Balances;
+    type BalanceConversion = ();
+    type OnChargeTransaction =
+        EVMCurrencyAdapter<AuthorAndTreasury<Test, MockAuthor, Treasury, AuthorShare>>;
+    type Event = Event;
+    type Precompiles = ();
+    type ChainId = ChainId;
+    type Runner = crate::runner::stack::Runner<Self>;
+}
+
+/// The account `address` maps to.
+pub fn account_id(address: H160) -> AccountId32 {
+    HashedAddressMapping::<BlakeTwo256>::into_account_id(address)
+}
+
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    frame_system::GenesisConfig::default()
+        .build_storage::<Test>()
+        .unwrap()
+        .into()
+}
@@ -1,7 +1,7 @@
 use crate::runner::Runner as RunnerT;
 use crate::{
-    AccountCodes, AccountStorages, AddressMapping, Config, Error, Event, FeeCalculator, Module,
-    PrecompileSet,
+    simulation_context, AccountCodes, AccountStorages, AddressMapping, BalanceConversion, Config,
+    Error, Event, FeeCalculator, Module, NonceStore, OnChargeEVMTransaction, PrecompileSet,
 };
 use evm::backend::Backend as BackendT;
 use evm::executor::{StackExecutor, StackState as StackStateT, StackSubstateMetadata};
@@ -9,13 +9,15 @@ use evm::{ExitError, ExitReason, Transfer};
 use ap_evm::{CallInfo, CreateInfo, ExecutionInfo, Log, Vicinity};
 use frame_support::{
     debug, ensure,
-    storage::{StorageDoubleMap, StorageMap},
+    storage::{StorageDoubleMap, StorageMap, StorageValue},
     traits::{Currency, ExistenceRequirement, Get},
 };
 use sha3::{Digest, Keccak256};
 use sp_core::{H160, H256, U256};
 use sp_runtime::traits::UniqueSaturatedInto;
-use sp_std::{boxed::Box, collections::btree_set::BTreeSet, marker::PhantomData, mem, vec::Vec};
+use sp_std::{
+    boxed::Box, collections::btree_set::BTreeSet, marker::PhantomData, mem, vec, vec::Vec,
+};
 
 #[derive(Default)]
 pub struct Runner<T: Config> {
@@ -66,7 +68,7 @@ impl<T: Config> Runner<T> {
             source_account.balance >= total_payment,
             Error::<T>::BalanceLow
         );
-        Module::<T>::withdraw_fee(&source, total_fee)?;
+        let fee = T::OnChargeTransaction::withdraw_fee(&source, total_fee)?;
         if let Some(nonce) = nonce {
             ensure!(source_account.nonce == nonce, Error::<T>::InvalidNonce);
         }
@@ -82,7 +84,7 @@ impl<T: Config> Runner<T> {
             gas_limit,
             actual_fee
         );
-        Module::<T>::deposit_fee(&source, total_fee.saturating_sub(actual_fee));
+        T::OnChargeTransaction::correct_and_deposit_fee(&source, actual_fee, fee);
         let state = executor.into_state();
         for address in state.substate.deletes {
             debug::debug!(
@@ -314,15 +316,21 @@ impl<'vicinity, 'config, T: Config> BackendT for SubstrateStackState<'vicinity,
     }
 
     fn block_number(&self) -> U256 {
+        if let Some(number) = simulation_context(|c| c.block_override.as_ref()?.number) {
+            return number;
+        }
         let number: u128 = frame_system::Module::<T>::block_number().unique_saturated_into();
         U256::from(number)
     }
 
     fn block_coinbase(&self) -> H160 {
-        H160::default()
+        simulation_context(|c| c.block_override.as_ref()?.coinbase).unwrap_or_default()
     }
 
     fn block_timestamp(&self) -> U256 {
+        if let Some(time) = simulation_context(|c| c.block_override.as_ref()?.time) {
+            return time;
+        }
         let now: u128 = pallet_timestamp::Module::<T>::get().unique_saturated_into();
         U256::from(now / 1000)
     }
@@ -332,7 +340,7 @@ impl<'vicinity, 'config, T: Config> BackendT for SubstrateStackState<'vicinity,
     }
 
     fn block_gas_limit(&self) -> U256 {
-        U256::zero()
+        simulation_context(|c| c.block_override.as_ref()?.gas_limit).unwrap_or_default()
     }
 
     fn chain_id(&self) -> U256 {
@@ -401,8 +409,7 @@ for SubstrateStackState<'vicinity, 'config, T>
     }
 
     fn inc_nonce(&mut self, address: H160) {
-        let account_id = T::AddressMapping::into_account_id(address);
-        frame_system::Module::<T>::inc_account_nonce(&account_id);
+        T::NonceStore::inc_nonce(&address);
     }
 
     fn set_storage(&mut self, address: H160, index: H256, value: H256) {
@@ -451,13 +458,34 @@ for SubstrateStackState<'vicinity, 'config, T>
     fn transfer(&mut self, transfer: Transfer) -> Result<(), ExitError> {
         let source = T::AddressMapping::into_account_id(transfer.source);
         let target = T::AddressMapping::into_account_id(transfer.target);
-        T::Currency::transfer(
-            &source,
-            &target,
-            transfer.value.low_u128().unique_saturated_into(),
-            ExistenceRequirement::AllowDeath,
-        )
-            .map_err(|_| ExitError::OutOfFund)
+        // Value that is not a whole native amount fails the transfer rather than being rounded:
+        // rounding down would destroy the remainder and rounding up would create value, and a
+        // per-account dust balance would have to be reconciled with `Currency` everywhere.
+        let value = T::BalanceConversion::to_native(transfer.value).ok_or_else(|| {
+            ExitError::Other("transfer value is not a whole native amount".into())
+        })?;
+        T::Currency::transfer(&source, &target, value, ExistenceRequirement::AllowDeath)
+            .map_err(|_| ExitError::OutOfFund)?;
+        if !transfer.value.is_zero()
+            && simulation_context(|c| Some(c.trace_transfers)).unwrap_or_default()
+        {
+            // Same shape as an ERC-20 `Transfer` event, emitted from the pseudo address geth
+            // uses for native value transfers.
+            let mut data = [0u8; 32];
+            transfer.value.to_big_endian(&mut data);
+            self.substate.log(
+                H160::repeat_byte(0xee),
+                vec![
+                    H256::from_slice(
+                        Keccak256::digest(b"Transfer(address,address,uint256)").as_slice(),
+                    ),
+                    H256::from(transfer.source),
+                    H256::from(transfer.target),
+                ],
+                data.to_vec(),
+            );
+        }
+        Ok(())
     }
 
     fn reset_balance(&mut self, _address: H160) {
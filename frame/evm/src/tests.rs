@@ -0,0 +1,177 @@
+use crate::mock::*;
+use crate::{
+    AccountCodes, AccountNonces, BalanceConversion, EvmNonceStore, NativeDecimals, NonceStore,
+    OnChargeEVMTransaction, RawEvent, SystemNonceStore,
+};
+use frame_support::{
+    traits::{Currency, Get},
+    StorageMap,
+};
+use sp_core::{H160, U256};
+
+frame_support::parameter_types! {
+    pub const TwelveDecimals: u8 = 12;
+    pub const NineteenDecimals: u8 = 19;
+}
+
+type Twelve = NativeDecimals<TwelveDecimals>;
+
+#[test]
+fn native_decimals_round_towards_the_account() {
+    let unit = U256::exp10(6);
+    assert_eq!(<Twelve as BalanceConversion<u64>>::wei_per_unit(), unit);
+    assert_eq!(<Twelve as BalanceConversion<u64>>::to_evm(5), Some(unit * 5));
+
+    let exact = unit * 5;
+    let inexact = exact + 1;
+    assert_eq!(<Twelve as BalanceConversion<u64>>::to_native(exact), Some(5));
+    assert_eq!(<Twelve as BalanceConversion<u64>>::to_native(inexact), None);
+    assert_eq!(<Twelve as BalanceConversion<u64>>::to_native_floor(inexact), Some(5));
+    assert_eq!(<Twelve as BalanceConversion<u64>>::to_native_ceil(exact), Some(5));
+    assert_eq!(<Twelve as BalanceConversion<u64>>::to_native_ceil(inexact), Some(6));
+}
+
+#[test]
+fn balance_conversion_rejects_overflow() {
+    let max = U256::from(u64::max_value());
+    assert_eq!(<() as BalanceConversion<u64>>::to_native(max), Some(u64::max_value()));
+    assert_eq!(<() as BalanceConversion<u64>>::to_native(max + 1), None);
+    assert_eq!(<() as BalanceConversion<u64>>::to_native_floor(U256::max_value()), None);
+    assert_eq!(<() as BalanceConversion<u128>>::to_native_ceil(U256::max_value()), None);
+    assert_eq!(
+        <Twelve as BalanceConversion<u128>>::to_evm(u128::max_value()),
+        Some(U256::from(u128::max_value()) * U256::exp10(6))
+    );
+}
+
+#[test]
+#[should_panic(expected = "NativeDecimals supports at most 18 decimals")]
+fn native_decimals_above_18_are_rejected() {
+    <NativeDecimals<NineteenDecimals> as BalanceConversion<u64>>::wei_per_unit();
+}
+
+#[test]
+fn system_nonce_store_uses_the_mapped_account() {
+    new_test_ext().execute_with(|| {
+        let address = H160::repeat_byte(1);
+        SystemNonceStore::<Test>::inc_nonce(&address);
+        SystemNonceStore::<Test>::inc_nonce(&address);
+        assert_eq!(SystemNonceStore::<Test>::nonce(&address), U256::from(2));
+        assert_eq!(System::account_nonce(&account_id(address)), 2);
+
+        assert!(SystemNonceStore::<Test>::set_nonce(&address, U256::from(7)).is_ok());
+        assert_eq!(System::account_nonce(&account_id(address)), 7);
+
+        // `Index` is a u64 in the mock.
+        let too_large = U256::from(u64::max_value()) + 1;
+        assert!(SystemNonceStore::<Test>::set_nonce(&address, too_large).is_err());
+        assert_eq!(SystemNonceStore::<Test>::nonce(&address), U256::from(7));
+        assert!(!AccountNonces::contains_key(&address));
+    });
+}
+
+#[test]
+fn evm_nonce_store_is_independent_of_the_system_nonce() {
+    new_test_ext().execute_with(|| {
+        let address = H160::repeat_byte(1);
+        EvmNonceStore::<Test>::inc_nonce(&address);
+        assert_eq!(EvmNonceStore::<Test>::nonce(&address), U256::one());
+        assert_eq!(System::account_nonce(&account_id(address)), 0);
+
+        assert!(EvmNonceStore::<Test>::set_nonce(&address, U256::max_value()).is_ok());
+        EvmNonceStore::<Test>::inc_nonce(&address);
+        assert_eq!(EvmNonceStore::<Test>::nonce(&address), U256::max_value());
+
+        assert!(EvmNonceStore::<Test>::set_nonce(&address, U256::zero()).is_ok());
+        assert!(!AccountNonces::contains_key(&address));
+    });
+}
+
+#[test]
+fn remove_account_clears_the_evm_nonce() {
+    new_test_ext().execute_with(|| {
+        let address = H160::repeat_byte(1);
+        AccountCodes::insert(&address, vec![0x00]);
+        EvmNonceStore::<Test>::inc_nonce(&address);
+
+        EVM::remove_account(&address);
+        assert!(!AccountCodes::contains_key(&address));
+        assert!(!AccountNonces::contains_key(&address));
+    });
+}
+
+#[test]
+fn copy_system_nonces_copies_the_listed_addresses() {
+    new_test_ext().execute_with(|| {
+        let listed = H160::repeat_byte(1);
+        let unlisted = H160::repeat_byte(2);
+        for address in &[listed, unlisted] {
+            SystemNonceStore::<Test>::inc_nonce(address);
+            SystemNonceStore::<Test>::inc_nonce(address);
+        }
+
+        EVM::copy_system_nonces(&[listed]);
+        assert_eq!(EvmNonceStore::<Test>::nonce(&listed), U256::from(2));
+        assert_eq!(EvmNonceStore::<Test>::nonce(&unlisted), U256::zero());
+        // The system nonce is left as it was.
+        assert_eq!(SystemNonceStore::<Test>::nonce(&listed), U256::from(2));
+    });
+}
+
+type Charge = <Test as crate::Config>::OnChargeTransaction;
+
+/// Fund `payer`, then charge and settle a fee of `fee`, of which `used` was actually used.
+fn charge_fee(payer: H160, fee: u64, used: u64) {
+    System::set_block_number(1);
+    let _ = Balances::deposit_creating(&account_id(payer), 1_000);
+    let paid = Charge::withdraw_fee(&payer, fee.into()).unwrap();
+    Charge::correct_and_deposit_fee(&payer, used.into(), paid);
+}
+
+fn fee_distributed(author: Option<H160>, to_author: u64, to_treasury: u64) -> bool {
+    let event = Event::pallet_evm(RawEvent::FeeDistributed(author, to_author, to_treasury));
+    System::events().iter().any(|record| record.event == event)
+}
+
+#[test]
+fn fees_are_split_between_author_and_treasury() {
+    new_test_ext().execute_with(|| {
+        let payer = H160::repeat_byte(1);
+        let author = H160::repeat_byte(2);
+        set_author(Some(author));
+        charge_fee(payer, 100, 100);
+
+        assert_eq!(Balances::free_balance(&account_id(payer)), 900);
+        assert_eq!(Balances::free_balance(&account_id(author)), 20);
+        assert_eq!(Balances::free_balance(&TreasuryAccount::get()), 80);
+        assert!(fee_distributed(Some(author), 20, 80));
+    });
+}
+
+#[test]
+fn unused_fees_are_refunded_before_the_split() {
+    new_test_ext().execute_with(|| {
+        let payer = H160::repeat_byte(1);
+        let author = H160::repeat_byte(2);
+        set_author(Some(author));
+        charge_fee(payer, 100, 60);
+
+        assert_eq!(Balances::free_balance(&account_id(payer)), 940);
+        assert_eq!(Balances::free_balance(&account_id(author)), 12);
+        assert_eq!(Balances::free_balance(&TreasuryAccount::get()), 48);
+        assert!(fee_distributed(Some(author), 12, 48));
+    });
+}
+
+#[test]
+fn fees_go_to_the_treasury_without_an_author() {
+    new_test_ext().execute_with(|| {
+        let payer = H160::repeat_byte(1);
+        set_author(None);
+        charge_fee(payer, 100, 100);
+
+        assert_eq!(Balances::free_balance(&account_id(payer)), 900);
+        assert_eq!(Balances::free_balance(&TreasuryAccount::get()), 100);
+        assert!(fee_distributed(None, 0, 100));
+    });
+}
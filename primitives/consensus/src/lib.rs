@@ -1,17 +1,123 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
 use codec::{Decode, Encode};
-use sp_core::H256;
-use sp_runtime::ConsensusEngineId;
+use sp_core::{H160, H256};
+use sp_runtime::{ConsensusEngineId, RuntimeDebug};
 use sp_std::vec::Vec;
 
 pub const FRONTIER_ENGINE_ID: ConsensusEngineId = [b'f', b'r', b'o', b'n'];
 
-#[derive(Decode, Encode, Clone, PartialEq, Eq)]
+/// Largest `extra_data` an Ethereum header may carry.
+pub const MAX_EXTRA_DATA_SIZE: usize = 32;
+
+#[derive(Decode, Encode, Clone, PartialEq, Eq, RuntimeDebug)]
 pub enum ConsensusLog {
+    /// Pre-runtime digest with the Ethereum header fields chosen by the block author.
+    #[codec(index = 0)]
+    PreBlock {
+        beneficiary: H160,
+        extra_data: Vec<u8>,
+        mix_hash: H256,
+    },
+    /// Consensus digest with the Ethereum block the runtime stored.
     #[codec(index = 1)]
     EndBlock {
         block_hash: H256,
         transaction_hashes: Vec<H256>,
     },
-}
\ No newline at end of file
+}
+
+/// Why the Frontier pre-runtime digests of a block are invalid.
+#[derive(Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum PreBlockError {
+    /// More than one Frontier pre-runtime digest.
+    Multiple,
+    /// A Frontier pre-runtime digest that is not a `PreBlock` log.
+    Invalid,
+    /// A `PreBlock` log with more than `MAX_EXTRA_DATA_SIZE` bytes of extra data.
+    ExtraDataTooLarge,
+}
+
+/// The `PreBlock` log among the pre-runtime digests of a block, if any. A block may carry at
+/// most one Frontier pre-runtime digest, and it must be a valid `PreBlock` log. Both block import
+/// and the runtime apply this rule.
+pub fn find_pre_block<'a, I>(pre_runtime_digests: I) -> Result<Option<ConsensusLog>, PreBlockError>
+    where
+        I: IntoIterator<Item = (ConsensusEngineId, &'a [u8])>,
+{
+    let mut pre_block = None;
+    for (id, mut data) in pre_runtime_digests {
+        if id != FRONTIER_ENGINE_ID {
+            continue;
+        }
+        if pre_block.is_some() {
+            return Err(PreBlockError::Multiple);
+        }
+        match ConsensusLog::decode(&mut data) {
+            Ok(ConsensusLog::PreBlock { extra_data, .. })
+                if extra_data.len() > MAX_EXTRA_DATA_SIZE =>
+            {
+                return Err(PreBlockError::ExtraDataTooLarge)
+            }
+            Ok(log @ ConsensusLog::PreBlock { .. }) if data.is_empty() => pre_block = Some(log),
+            _ => return Err(PreBlockError::Invalid),
+        }
+    }
+    Ok(pre_block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pre_block(extra_data: Vec<u8>) -> Vec<u8> {
+        ConsensusLog::PreBlock {
+            beneficiary: H160::repeat_byte(0x01),
+            extra_data,
+            mix_hash: H256::zero(),
+        }
+        .encode()
+    }
+
+    fn find(
+        digests: &[(ConsensusEngineId, Vec<u8>)],
+    ) -> Result<Option<ConsensusLog>, PreBlockError> {
+        find_pre_block(digests.iter().map(|(id, data)| (*id, &data[..])))
+    }
+
+    #[test]
+    fn finds_single_pre_block() {
+        let log = pre_block(vec![0; MAX_EXTRA_DATA_SIZE]);
+        assert_eq!(find(&[]), Ok(None));
+        assert_eq!(find(&[(*b"aura", vec![1, 2, 3])]), Ok(None));
+        assert_eq!(
+            find(&[(*b"aura", vec![1, 2, 3]), (FRONTIER_ENGINE_ID, log.clone())]),
+            Ok(Some(ConsensusLog::decode(&mut &log[..]).unwrap()))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_pre_blocks() {
+        let log = pre_block(Vec::new());
+        assert_eq!(
+            find(&[(FRONTIER_ENGINE_ID, log.clone()), (FRONTIER_ENGINE_ID, log.clone())]),
+            Err(PreBlockError::Multiple)
+        );
+        assert_eq!(
+            find(&[(FRONTIER_ENGINE_ID, pre_block(vec![0; MAX_EXTRA_DATA_SIZE + 1]))]),
+            Err(PreBlockError::ExtraDataTooLarge)
+        );
+        let end_block = ConsensusLog::EndBlock {
+            block_hash: H256::zero(),
+            transaction_hashes: Vec::new(),
+        };
+        assert_eq!(
+            find(&[(FRONTIER_ENGINE_ID, end_block.encode())]),
+            Err(PreBlockError::Invalid)
+        );
+        assert_eq!(find(&[(FRONTIER_ENGINE_ID, vec![0])]), Err(PreBlockError::Invalid));
+        let mut trailing = log;
+        trailing.push(0);
+        assert_eq!(find(&[(FRONTIER_ENGINE_ID, trailing)]), Err(PreBlockError::Invalid));
+    }
+}
@@ -6,7 +6,7 @@ use codec::{Decode, Encode};
 use evm::ExitReason;
 #[cfg(feature = "std")]
 use serde::{Deserialize, Serialize};
-use sp_core::{H160, U256};
+use sp_core::{H160, H256, U256};
 use sp_std::vec::Vec;
 
 pub use evm::backend::{Basic as Account, Log};
@@ -36,4 +36,45 @@ pub type CreateInfo = ExecutionInfo<H160>;
 pub enum CallOrCreateInfo {
     Call(CallInfo),
     Create(CreateInfo),
+}
+
+#[derive(Clone, Eq, PartialEq, Encode, Decode, Default)]
+#[cfg_attr(feature = "std", derive(Debug, Serialize, Deserialize))]
+pub struct StateOverride {
+    pub balance: Option<U256>,
+    pub nonce: Option<U256>,
+    pub code: Option<Vec<u8>>,
+    /// Replaces the whole storage of the account.
+    pub state: Option<Vec<(H256, H256)>>,
+    /// Patches the given storage slots, leaving the others untouched.
+    pub state_diff: Option<Vec<(H256, H256)>>,
+}
+
+#[derive(Clone, Eq, PartialEq, Encode, Decode, Default)]
+#[cfg_attr(feature = "std", derive(Debug, Serialize, Deserialize))]
+pub struct BlockOverride {
+    pub number: Option<U256>,
+    pub time: Option<U256>,
+    pub coinbase: Option<H160>,
+    pub gas_limit: Option<U256>,
+}
+
+#[derive(Clone, Eq, PartialEq, Encode, Decode, Default)]
+#[cfg_attr(feature = "std", derive(Debug, Serialize, Deserialize))]
+pub struct SimulateCall {
+    pub from: H160,
+    pub to: Option<H160>,
+    pub data: Vec<u8>,
+    pub value: U256,
+    pub gas_limit: U256,
+    pub gas_price: Option<U256>,
+    pub nonce: Option<U256>,
+}
+
+#[derive(Clone, Eq, PartialEq, Encode, Decode, Default)]
+#[cfg_attr(feature = "std", derive(Debug, Serialize, Deserialize))]
+pub struct SimulateBlock {
+    pub state_overrides: Vec<(H160, StateOverride)>,
+    pub block_override: Option<BlockOverride>,
+    pub calls: Vec<SimulateCall>,
 }
\ No newline at end of file
@@ -4,6 +4,7 @@ use codec::{Decode, Encode};
 use ethereum::{Block as EthereumBlock, Log};
 use ethereum_types::Bloom;
 use sp_core::{H160, H256, U256};
+use sp_runtime::traits::Block as BlockT;
 use sp_std::vec::Vec;
 
 #[derive(Eq, PartialEq, Clone, Encode, Decode, sp_runtime::RuntimeDebug)]
@@ -32,6 +33,9 @@ impl Default for TransactionStatus {
 }
 
 sp_api::decl_runtime_apis! {
+    /// Version 2 adds a CREATE2 `salt` to `create`, and `call_with_overrides`, `simulate` and
+    /// `extrinsic_filter`.
+    #[api_version(2)]
     pub trait EthereumRuntimeRPCApi {
         fn chain_id() -> u64;
         fn account_basic(address: H160) -> ap_evm::Account;
@@ -49,6 +53,7 @@ sp_api::decl_runtime_apis! {
             nonce: Option<U256>,
             estimate: bool,
         ) -> Result<ap_evm::CallInfo, sp_runtime::DispatchError>;
+        #[changed_in(2)]
         fn create(
             from: H160,
             data: Vec<u8>,
@@ -58,6 +63,32 @@ sp_api::decl_runtime_apis! {
             nonce: Option<U256>,
             estimate: bool,
         ) -> Result<ap_evm::CreateInfo, sp_runtime::DispatchError>;
+        fn create(
+            from: H160,
+            data: Vec<u8>,
+            value: U256,
+            gas_limit: U256,
+            gas_price: Option<U256>,
+            nonce: Option<U256>,
+            salt: Option<H256>,
+            estimate: bool,
+        ) -> Result<ap_evm::CreateInfo, sp_runtime::DispatchError>;
+        fn call_with_overrides(
+            from: H160,
+            to: Option<H160>,
+            data: Vec<u8>,
+            value: U256,
+            gas_limit: U256,
+            gas_price: Option<U256>,
+            nonce: Option<U256>,
+            estimate: bool,
+            state_overrides: Vec<(H160, ap_evm::StateOverride)>,
+            block_override: Option<ap_evm::BlockOverride>,
+        ) -> Result<ap_evm::CallOrCreateInfo, sp_runtime::DispatchError>;
+        fn simulate(
+            blocks: Vec<ap_evm::SimulateBlock>,
+            trace_transfers: bool,
+        ) -> Vec<Vec<Result<ap_evm::CallOrCreateInfo, sp_runtime::DispatchError>>>;
         fn current_block() -> Option<EthereumBlock>;
         fn current_receipts() -> Option<Vec<ethereum::Receipt>>;
         fn current_transaction_statuses() -> Option<Vec<TransactionStatus>>;
@@ -67,6 +98,7 @@ sp_api::decl_runtime_apis! {
             Option<Vec<TransactionStatus>>
         );
         fn current_block_gas_limit() -> U256;
+        fn extrinsic_filter(xts: Vec<<Block as BlockT>::Extrinsic>) -> Vec<ethereum::Transaction>;
     }
 }
 